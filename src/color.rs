@@ -0,0 +1,229 @@
+use std::borrow::Cow;
+use std::env;
+use std::io::IsTerminal;
+
+
+// Whether console output is colored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    Auto,  // Color when stdout is a terminal; honours NO_COLOR, CLICOLOR_FORCE and TERM=dumb
+}
+
+impl ColorChoice {
+    // Resolves the choice for stdout. For Auto, CLICOLOR_FORCE (set and not "0") forces color on,
+    // otherwise NO_COLOR (set and not empty) or TERM=dumb turn it off.
+    pub fn enabled_for_stdout(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => auto_enabled(std::io::stdout().is_terminal(), |name| env::var(name).ok()),
+        }
+    }
+}
+
+fn auto_enabled(is_terminal: bool, var: impl Fn(&str) -> Option<String>) -> bool {
+    if var("CLICOLOR_FORCE").is_some_and(|v| v != "0") {
+        return true;
+    }
+    if var("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if var("TERM").is_some_and(|term| term == "dumb") {
+        return false;
+    }
+    is_terminal
+}
+
+
+// How many colors the terminal can show. The CONSOLE_COLOR_* constants use truecolor sequences,
+// which are mapped down to the nearest 256 or 16 color code on terminals that lack them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    // COLORTERM=truecolor/24bit -> TrueColor, a TERM containing "256color" -> Ansi256, otherwise Ansi16
+    pub fn detect() -> Self {
+        detect_depth(|name| env::var(name).ok())
+    }
+
+    // Rewrites the SGR escape sequences in `escape` so they only use colors of this depth
+    pub fn adapt<'a>(&self, escape: &'a str) -> Cow<'a, str> {
+        if *self == ColorDepth::TrueColor || !escape.contains('\x1b') {
+            return Cow::Borrowed(escape);
+        }
+
+        let mut out = String::with_capacity(escape.len());
+        let mut rest = escape;
+        while let Some(start) = rest.find("\x1b[") {
+            out.push_str(&rest[..start]);
+            let sequence = &rest[start + 2..];
+            match sequence.find('m') {
+                Some(end) if sequence[..end].bytes().all(|b| b.is_ascii_digit() || b == b';') => {
+                    out.push_str("\x1b[");
+                    out.push_str(&self.adapt_params(&sequence[..end]));
+                    out.push('m');
+                    rest = &sequence[end + 1..];
+                }
+                _ => {
+                    // Not an SGR sequence, keep it as is
+                    out.push_str("\x1b[");
+                    rest = sequence;
+                }
+            }
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+
+    fn adapt_params(&self, params: &str) -> String {
+        let params: Vec<u32> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let mut out: Vec<String> = Vec::with_capacity(params.len());
+        let mut i = 0;
+
+        while i < params.len() {
+            let code = params[i];
+            let is_extended = code == 38 || code == 48;
+
+            if is_extended && params.get(i + 1) == Some(&2) && i + 4 < params.len() {
+                let rgb = (params[i + 2], params[i + 3], params[i + 4]);
+                out.push(self.encode(code, rgb));
+                i += 5;
+            } else if is_extended && params.get(i + 1) == Some(&5) && i + 2 < params.len() {
+                match self {
+                    ColorDepth::Ansi16 => out.push(self.encode(code, ansi256_to_rgb(params[i + 2]))),
+                    _ => out.push(format!("{};5;{}", code, params[i + 2])),
+                }
+                i += 3;
+            } else {
+                out.push(code.to_string());
+                i += 1;
+            }
+        }
+        out.join(";")
+    }
+
+    // `base` is 38 (foreground) or 48 (background)
+    fn encode(&self, base: u32, (r, g, b): (u32, u32, u32)) -> String {
+        match self {
+            ColorDepth::TrueColor => format!("{};2;{};{};{}", base, r, g, b),
+            ColorDepth::Ansi256 => format!("{};5;{}", base, rgb_to_ansi256(r, g, b)),
+            ColorDepth::Ansi16 => {
+                let index = rgb_to_ansi16(r, g, b);
+                let offset = if base == 38 { 30 } else { 40 };
+                if index < 8 {
+                    (offset + index).to_string()
+                } else {
+                    (offset + 60 + index - 8).to_string()
+                }
+            }
+        }
+    }
+}
+
+fn detect_depth(var: impl Fn(&str) -> Option<String>) -> ColorDepth {
+    if var("COLORTERM").is_some_and(|v| v == "truecolor" || v == "24bit") {
+        ColorDepth::TrueColor
+    } else if var("TERM").is_some_and(|term| term.contains("256color")) {
+        ColorDepth::Ansi256
+    } else {
+        ColorDepth::Ansi16
+    }
+}
+
+// xterm's default values for the 16 basic colors, used to map 256-color codes 0-15 back to RGB
+const ANSI16_PALETTE: [(u32, u32, u32); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+const CUBE_LEVELS: [u32; 6] = [0, 95, 135, 175, 215, 255];
+
+fn rgb_to_ansi256(r: u32, g: u32, b: u32) -> u32 {
+    let cube_index = |v: u32| CUBE_LEVELS.iter().enumerate().min_by_key(|(_, &l)| l.abs_diff(v)).unwrap().0 as u32;
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = 16 + 36 * ri + 6 * gi + bi;
+
+    // Grays are better served by the 24-step gray ramp
+    let gray_level = ((r + g + b) / 3).saturating_sub(3) / 10;
+    let gray_index = 232 + gray_level.min(23);
+    let gray_value = 8 + 10 * gray_level.min(23);
+
+    let cube_rgb = (CUBE_LEVELS[ri as usize], CUBE_LEVELS[gi as usize], CUBE_LEVELS[bi as usize]);
+    if distance((r, g, b), (gray_value, gray_value, gray_value)) < distance((r, g, b), cube_rgb) {
+        gray_index
+    } else {
+        cube
+    }
+}
+
+fn ansi256_to_rgb(index: u32) -> (u32, u32, u32) {
+    match index {
+        0..=15 => ANSI16_PALETTE[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            (CUBE_LEVELS[(i / 36) as usize], CUBE_LEVELS[(i / 6 % 6) as usize], CUBE_LEVELS[(i % 6) as usize])
+        }
+        _ => {
+            let v = 8 + 10 * (index.min(255) - 232);
+            (v, v, v)
+        }
+    }
+}
+
+// Nearest-by-distance picks grays for most pastel colors, so classify by hue instead: low
+// saturation maps to the gray shades, otherwise each channel close to the brightest one sets its bit
+fn rgb_to_ansi16(r: u32, g: u32, b: u32) -> u32 {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    if max - min < 32 {
+        return match max {
+            0..=63 => 0,     // black
+            64..=159 => 8,   // dark gray
+            160..=223 => 7,  // light gray
+            _ => 15,         // white
+        };
+    }
+
+    let threshold = max * 65 / 100;
+    let bits = (r >= threshold) as u32 | ((g >= threshold) as u32) << 1 | ((b >= threshold) as u32) << 2;
+    if max > 191 { bits + 8 } else { bits }
+}
+
+fn distance(a: (u32, u32, u32), b: (u32, u32, u32)) -> u32 {
+    let d = |x: u32, y: u32| x.abs_diff(y).pow(2);
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+
+// Removes ANSI escape sequences (CSI such as colors, and two-byte ESC sequences)
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // Two-byte sequences are dropped whole, CSI sequences run up to a final byte in '@'..='~'
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    Cow::Owned(out)
+}
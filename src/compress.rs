@@ -0,0 +1,125 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+// How rotated log files are compressed. Gzip and Zstd need the cargo features of the same name.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,  // Rotated files stay plain text
+    #[cfg(feature = "gzip")]
+    Gzip,  // `app.log.<timestamp>.gz`
+    #[cfg(feature = "zstd")]
+    Zstd,  // `app.log.<timestamp>.zst`
+}
+
+impl Compression {
+    // Extension added to the compressed file, including the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    // Compresses `path` into `path<extension>` and removes `path`. The output is written under a
+    // temporary name and renamed once complete, so a failure leaves the original file untouched.
+    pub fn compress_file(&self, path: &Path) -> io::Result<PathBuf> {
+        if *self == Compression::None {
+            return Ok(path.to_path_buf());
+        }
+        let target = PathBuf::from(format!("{}{}", path.display(), self.extension()));
+        let partial = PathBuf::from(format!("{}.tmp", target.display()));
+
+        let result = self.encode(path, &partial).and_then(|()| fs::rename(&partial, &target));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        fs::remove_file(path)?;
+        Ok(target)
+    }
+
+    fn encode(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut input = BufReader::new(File::open(from)?);
+        let mut output = BufWriter::new(File::create(to)?);
+        let output = match self {
+            Compression::None => {
+                io::copy(&mut input, &mut output)?;
+                output
+            }
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(output, 0)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+        // On disk before the original is removed
+        output.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+
+// Background thread compressing rotated files one at a time, so rotation never waits for it.
+// Started on the first rotation; dropping it finishes the queued files and joins the thread.
+pub(crate) struct Compressor {
+    compression: Compression,
+    worker: Option<(Sender<PathBuf>, JoinHandle<()>)>,
+}
+
+impl Compressor {
+    pub(crate) fn new(compression: Compression) -> Self {
+        Compressor { compression, worker: None }
+    }
+
+    pub(crate) fn submit(&mut self, path: PathBuf) {
+        if self.compression == Compression::None {
+            return;
+        }
+        if self.worker.is_none() {
+            let compression = self.compression;
+            let (sender, receiver) = mpsc::channel::<PathBuf>();
+            let spawned = thread::Builder::new()
+                .name("mlog-compress".to_string())
+                .spawn(move || {
+                    for path in receiver {
+                        // Nothing to log to from here; the rotated file stays as it is
+                        if let Err(e) = compression.compress_file(&path) {
+                            eprintln!("mlog: failed to compress {}: {}", path.display(), e);
+                        }
+                    }
+                });
+            match spawned {
+                Ok(handle) => self.worker = Some((sender, handle)),
+                Err(e) => {
+                    eprintln!("mlog: failed to start log compression: {}", e);
+                    return;
+                }
+            }
+        }
+        if let Some((ref sender, _)) = self.worker {
+            let _ = sender.send(path);
+        }
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        if let Some((sender, handle)) = self.worker.take() {
+            drop(sender);  // ends the worker's loop once the queue is empty
+            let _ = handle.join();
+        }
+    }
+}
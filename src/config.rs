@@ -0,0 +1,255 @@
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use chrono::format::{Item, StrftimeItems};
+
+use crate::filter::{TargetFilter, FILTER_ENV};
+use crate::{ColorChoice, Compression, IoErrorPolicy, LevelSet, LogConfig, LogLevel, OutputFormat, RotationInterval};
+
+// Environment variables read by `LogConfig::from_env` / `LogConfig::with_env` (and FILTER_ENV)
+pub const ENV_LEVEL: &str = "MLOG_LEVEL";              // trace, debug, info, success, warn, error, crit
+pub const ENV_FILE: &str = "MLOG_FILE";                // log file path (template), empty for no file
+pub const ENV_FILE_PER_SESSION: &str = "MLOG_FILE_PER_SESSION";  // boolean, a fresh file for every session
+pub const ENV_FILE_LINK: &str = "MLOG_FILE_LINK";      // symlink to the active file, empty for none
+pub const ENV_FILE_FORMAT: &str = "MLOG_FILE_FORMAT";  // text, json, logfmt
+pub const ENV_FILE_LEVELS: &str = "MLOG_FILE_LEVELS";  // level set written to the file, e.g. crit+success
+pub const ENV_FILE_MAX_SIZE: &str = "MLOG_FILE_MAX_SIZE";  // rotation size, e.g. 10MB, 0 for no limit
+pub const ENV_FILE_ROTATE: &str = "MLOG_FILE_ROTATE";  // hourly, daily, midnight, never
+pub const ENV_FILE_COMPRESS: &str = "MLOG_FILE_COMPRESS";  // none, gzip, zstd
+pub const ENV_FILE_KEEP_FILES: &str = "MLOG_FILE_KEEP_FILES";  // most rotated files kept
+pub const ENV_FILE_KEEP_SIZE: &str = "MLOG_FILE_KEEP_SIZE";  // most bytes of rotated files kept, e.g. 1GB
+pub const ENV_FILE_KEEP_AGE: &str = "MLOG_FILE_KEEP_AGE";  // oldest rotated file kept, e.g. 7d, 12h
+pub const ENV_CONSOLE: &str = "MLOG_CONSOLE";          // true/false, 1/0, yes/no, on/off
+pub const ENV_CONSOLE_LEVELS: &str = "MLOG_CONSOLE_LEVELS";  // level set written to the console
+pub const ENV_COLOR: &str = "MLOG_COLOR";              // always, never, auto
+pub const ENV_ASYNC: &str = "MLOG_ASYNC";              // boolean, as MLOG_CONSOLE
+pub const ENV_TIME_FORMAT: &str = "MLOG_TIME_FORMAT";  // strftime format
+pub const ENV_APP: &str = "MLOG_APP";                  // application name
+pub const ENV_ON_IO_ERROR: &str = "MLOG_ON_IO_ERROR";  // ignore, stderr, retry
+
+
+// A configuration value that could not be used, e.g. `MLOG_ASYNC=maybe`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub key: String,    // Variable (or setting) the value came from
+    pub value: String,  // Empty when the problem is not a single value, e.g. an unreadable file
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.value.is_empty() {
+            write!(f, "invalid {}: {}", self.key, self.message)
+        } else {
+            write!(f, "invalid {}={:?}: {}", self.key, self.value, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+
+impl LogConfig {
+    // Default config with the MLOG_* environment variables applied
+    pub fn from_env() -> Result<Self, ConfigError> {
+        LogConfig::default().with_env()
+    }
+
+    // Overrides the settings whose MLOG_* variable is set, keeping the rest of `self`
+    pub fn with_env(self) -> Result<Self, ConfigError> {
+        self.with_vars(|name| env::var(name).ok())
+    }
+
+    pub(crate) fn with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        if let Some(value) = var(ENV_LEVEL) {
+            self.log_level = parse_level(ENV_LEVEL, &value)?;
+        }
+        if let Some(value) = var(FILTER_ENV) {
+            let filter = value.trim();
+            TargetFilter::parse(filter, self.log_level)
+                .map_err(|e| error(FILTER_ENV, &value, &e.message))?;
+            self.filter = if filter.is_empty() { None } else { Some(filter.to_string()) };
+        }
+        if let Some(value) = var(ENV_FILE) {
+            let path = value.trim();
+            self.log_filepath = if path.is_empty() { None } else { Some(path.to_string()) };
+        }
+        if let Some(value) = var(ENV_FILE_PER_SESSION) {
+            self.file_per_session = parse_bool(ENV_FILE_PER_SESSION, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_LINK) {
+            let link = value.trim();
+            self.current_link = if link.is_empty() { None } else { Some(link.to_string()) };
+        }
+        if let Some(value) = var(ENV_FILE_FORMAT) {
+            self.file_format = parse_file_format(ENV_FILE_FORMAT, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_LEVELS) {
+            self.file_levels = parse_levels(ENV_FILE_LEVELS, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_MAX_SIZE) {
+            self.rotation.max_size = parse_size(ENV_FILE_MAX_SIZE, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_ROTATE) {
+            self.rotation.interval = parse_rotation_interval(ENV_FILE_ROTATE, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_COMPRESS) {
+            self.compression = parse_compression(ENV_FILE_COMPRESS, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_KEEP_FILES) {
+            self.retention.max_files = Some(parse_count(ENV_FILE_KEEP_FILES, &value)?);
+        }
+        if let Some(value) = var(ENV_FILE_KEEP_SIZE) {
+            self.retention.max_total_size = parse_size(ENV_FILE_KEEP_SIZE, &value)?;
+        }
+        if let Some(value) = var(ENV_FILE_KEEP_AGE) {
+            self.retention.max_age = Some(parse_duration(ENV_FILE_KEEP_AGE, &value)?);
+        }
+        if let Some(value) = var(ENV_CONSOLE) {
+            self.console_flag = parse_bool(ENV_CONSOLE, &value)?;
+        }
+        if let Some(value) = var(ENV_CONSOLE_LEVELS) {
+            self.console_levels = parse_levels(ENV_CONSOLE_LEVELS, &value)?;
+        }
+        if let Some(value) = var(ENV_COLOR) {
+            self.color = parse_color(ENV_COLOR, &value)?;
+        }
+        if let Some(value) = var(ENV_ASYNC) {
+            self.async_flag = parse_bool(ENV_ASYNC, &value)?;
+        }
+        if let Some(value) = var(ENV_TIME_FORMAT) {
+            self.time_format = parse_time_format(ENV_TIME_FORMAT, &value)?;
+        }
+        if let Some(value) = var(ENV_APP) {
+            self.application_name = value;
+        }
+        if let Some(value) = var(ENV_ON_IO_ERROR) {
+            self.io_error_policy = parse_io_error_policy(ENV_ON_IO_ERROR, &value)?;
+        }
+        Ok(self)
+    }
+}
+
+fn error(key: &str, value: &str, message: &str) -> ConfigError {
+    ConfigError { key: key.to_string(), value: value.to_string(), message: message.to_string() }
+}
+
+pub(crate) fn parse_level(key: &str, value: &str) -> Result<LogLevel, ConfigError> {
+    LogLevel::from_name(value).ok_or_else(|| error(key, value, "expected one of trace, debug, info, success, warn, error, crit"))
+}
+
+// A LevelSet expression: `warn`, `crit+success`, `all-success`
+pub(crate) fn parse_levels(key: &str, value: &str) -> Result<LevelSet, ConfigError> {
+    LevelSet::parse(value).map_err(|message| error(key, value, &message))
+}
+
+pub(crate) fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(error(key, value, "expected true or false (also 1/0, yes/no, on/off)")),
+    }
+}
+
+pub(crate) fn parse_color(key: &str, value: &str) -> Result<ColorChoice, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        "auto" => Ok(ColorChoice::Auto),
+        _ => Err(error(key, value, "expected always, never or auto")),
+    }
+}
+
+pub(crate) fn parse_file_format(key: &str, value: &str) -> Result<OutputFormat, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "text" => Ok(OutputFormat::Text),
+        "json" => Ok(OutputFormat::Json),
+        "logfmt" => Ok(OutputFormat::Logfmt),
+        _ => Err(error(key, value, "expected text, json or logfmt")),
+    }
+}
+
+// Byte count with an optional binary unit: `1048576`, `512KB`, `10 MB`, `1GiB`. 0 means no limit.
+pub(crate) fn parse_size(key: &str, value: &str) -> Result<Option<u64>, ConfigError> {
+    let text = value.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (digits, unit) = text.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(error(key, value, "expected a size such as 1048576, 512KB, 10MB or 1GB")),
+    };
+    let size = digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| error(key, value, "expected a size such as 1048576, 512KB, 10MB or 1GB"))?;
+    Ok(if size == 0 { None } else { Some(size) })
+}
+
+pub(crate) fn parse_count(key: &str, value: &str) -> Result<usize, ConfigError> {
+    value.trim().parse().map_err(|_| error(key, value, "expected a whole number"))
+}
+
+// A number with a unit: `90s`, `30m`, `12h`, `7d`, `2w` (plain numbers are seconds)
+pub(crate) fn parse_duration(key: &str, value: &str) -> Result<Duration, ConfigError> {
+    let text = value.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (digits, unit) = text.split_at(split);
+    let seconds: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(error(key, value, "expected a duration such as 90s, 30m, 12h, 7d or 2w")),
+    };
+    digits.parse::<u64>().ok().and_then(|n| n.checked_mul(seconds)).map(Duration::from_secs)
+        .ok_or_else(|| error(key, value, "expected a duration such as 90s, 30m, 12h, 7d or 2w"))
+}
+
+pub(crate) fn parse_rotation_interval(key: &str, value: &str) -> Result<Option<RotationInterval>, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "hourly" => Ok(Some(RotationInterval::Hourly)),
+        "daily" => Ok(Some(RotationInterval::Daily)),
+        "midnight" => Ok(Some(RotationInterval::Midnight)),
+        "never" => Ok(None),
+        _ => Err(error(key, value, "expected hourly, daily, midnight or never")),
+    }
+}
+
+// Gzip and Zstd are only accepted when built with the matching feature
+pub(crate) fn parse_compression(key: &str, value: &str) -> Result<Compression, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "none" => Ok(Compression::None),
+        #[cfg(feature = "gzip")]
+        "gzip" | "gz" => Ok(Compression::Gzip),
+        #[cfg(feature = "zstd")]
+        "zstd" | "zst" => Ok(Compression::Zstd),
+        #[cfg(not(feature = "gzip"))]
+        "gzip" | "gz" => Err(error(key, value, "mlog was built without the \"gzip\" feature")),
+        #[cfg(not(feature = "zstd"))]
+        "zstd" | "zst" => Err(error(key, value, "mlog was built without the \"zstd\" feature")),
+        _ => Err(error(key, value, "expected none, gzip or zstd")),
+    }
+}
+
+// Callbacks can only be set in code; `retry` uses the default attempts and backoff
+pub(crate) fn parse_io_error_policy(key: &str, value: &str) -> Result<IoErrorPolicy, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "ignore" => Ok(IoErrorPolicy::Ignore),
+        "stderr" => Ok(IoErrorPolicy::Stderr),
+        "retry" => Ok(IoErrorPolicy::retry()),
+        _ => Err(error(key, value, "expected ignore, stderr or retry")),
+    }
+}
+
+// Rejects formats chrono cannot render (it would panic while formatting a record)
+pub(crate) fn parse_time_format(key: &str, value: &str) -> Result<String, ConfigError> {
+    if value.is_empty() {
+        return Err(error(key, value, "time format is empty"));
+    }
+    if StrftimeItems::new(value).any(|item| matches!(item, Item::Error)) {
+        return Err(error(key, value, "not a valid strftime format"));
+    }
+    Ok(value.to_string())
+}
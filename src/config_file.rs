@@ -0,0 +1,345 @@
+// TOML config files (cargo feature "toml"): `LogConfig::from_file`, and a watcher that polls the
+// file's mtime and applies level/filter edits to a running logger.
+//
+//     level = "info"
+//     filter = "info,myapp::net=warn"
+//     application_name = "myapp"
+//     time_format = "%Y-%m-%d %H:%M:%S%.3f"
+//     on_io_error = "stderr"
+//
+//     [console]
+//     enabled = true
+//     color = "auto"
+//     pattern = "[{time}] {level:<7} {msg}"
+//     levels = "all-success"
+//
+//     [file]
+//     path = "logs/{app}/{date}.log"
+//     per_session = false
+//     current_link = "logs/current.log"
+//     format = "json"
+//     levels = "crit+success"
+//     max_size = "10MB"
+//     rotate = "midnight"
+//     compress = "gzip"
+//     keep_files = 30
+//     keep_size = "1GB"
+//     keep_age = "14d"
+//
+//     [async]
+//     enabled = true
+//     buffer_capacity = 1024
+//     overflow_policy = "drop_newest"
+//     max_batch_size = 64
+//     max_latency_ms = 100
+//     shutdown_timeout_ms = 5000
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use toml::{Table, Value};
+
+use crate::config::{parse_bool, parse_color, parse_compression, parse_duration, parse_file_format, parse_io_error_policy, parse_level, parse_levels, parse_rotation_interval, parse_size, parse_time_format, ConfigError};
+use crate::{LogConfig, LogLevel, LogRecord, Logger, OverflowPolicy, TargetFilter, CONSOLE_COLOR_INFO, CONSOLE_COLOR_PINK, FILTER_ENV};
+
+impl LogConfig {
+    // Defaults overridden by the settings in a TOML file. Unknown keys are errors, so typos do not go unnoticed.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ConfigError {
+            key: path.display().to_string(),
+            value: String::new(),
+            message: format!("cannot read config file: {}", e),
+        })?;
+        LogConfig::from_toml(&text).map_err(|mut e| {
+            e.key = format!("{}: {}", path.display(), e.key);
+            e
+        })
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let table: Table = text.parse().map_err(|e: toml::de::Error| ConfigError {
+            key: "toml".to_string(),
+            value: String::new(),
+            message: e.message().to_string(),
+        })?;
+
+        let mut config = LogConfig::default();
+        let mut root = Section::new("", &table);
+        if let Some(level) = root.string("level")? {
+            config.log_level = parse_level("level", &level)?;
+        }
+        if let Some(filter) = root.string("filter")? {
+            TargetFilter::parse(&filter, config.log_level).map_err(|e| invalid("filter", &filter, &e.message))?;
+            config.filter = Some(filter);
+        }
+        if let Some(name) = root.string("application_name")? {
+            config.application_name = name;
+        }
+        if let Some(format) = root.string("time_format")? {
+            config.time_format = parse_time_format("time_format", &format)?;
+        }
+        if let Some(policy) = root.string("on_io_error")? {
+            config.io_error_policy = parse_io_error_policy("on_io_error", &policy)?;
+        }
+
+        if let Some(mut console) = root.section("console")? {
+            if let Some(enabled) = console.bool("enabled")? {
+                config.console_flag = enabled;
+            }
+            if let Some(color) = console.string("color")? {
+                config.color = parse_color("console.color", &color)?;
+            }
+            config.console_pattern = console.string("pattern")?.or(config.console_pattern);
+            if let Some(levels) = console.string("levels")? {
+                config.console_levels = parse_levels("console.levels", &levels)?;
+            }
+            console.finish()?;
+        }
+
+        if let Some(mut file) = root.section("file")? {
+            config.log_filepath = file.string("path")?.or(config.log_filepath);
+            if let Some(per_session) = file.bool("per_session")? {
+                config.file_per_session = per_session;
+            }
+            config.current_link = file.string("current_link")?.or(config.current_link);
+            if let Some(format) = file.string("format")? {
+                config.file_format = parse_file_format("file.format", &format)?;
+            }
+            config.file_pattern = file.string("pattern")?.or(config.file_pattern);
+            if let Some(levels) = file.string("levels")? {
+                config.file_levels = parse_levels("file.levels", &levels)?;
+            }
+            if let Some(size) = file.size("max_size")? {
+                config.rotation.max_size = size;
+            }
+            if let Some(interval) = file.string("rotate")? {
+                config.rotation.interval = parse_rotation_interval("file.rotate", &interval)?;
+            }
+            if let Some(compression) = file.string("compress")? {
+                config.compression = parse_compression("file.compress", &compression)?;
+            }
+            if let Some(count) = file.integer("keep_files")? {
+                config.retention.max_files = Some(count as usize);
+            }
+            if let Some(size) = file.size("keep_size")? {
+                config.retention.max_total_size = size;
+            }
+            if let Some(age) = file.string("keep_age")? {
+                config.retention.max_age = Some(parse_duration("file.keep_age", &age)?);
+            }
+            file.finish()?;
+        }
+
+        if let Some(mut section) = root.section("async")? {
+            if let Some(enabled) = section.bool("enabled")? {
+                config.async_flag = enabled;
+            }
+            if let Some(capacity) = section.integer("buffer_capacity")? {
+                config.buffer_capacity = capacity as usize;
+            }
+            if let Some(policy) = section.string("overflow_policy")? {
+                config.overflow_policy = parse_overflow_policy("async.overflow_policy", &policy)?;
+            }
+            if let Some(size) = section.integer("max_batch_size")? {
+                config.max_batch_size = size as usize;
+            }
+            if let Some(ms) = section.integer("max_latency_ms")? {
+                config.max_latency = Duration::from_millis(ms);
+            }
+            if let Some(ms) = section.integer("shutdown_timeout_ms")? {
+                config.shutdown_timeout = Duration::from_millis(ms);
+            }
+            section.finish()?;
+        }
+
+        root.finish()?;
+        Ok(config)
+    }
+}
+
+fn invalid(key: &str, value: &str, message: &str) -> ConfigError {
+    ConfigError { key: key.to_string(), value: value.to_string(), message: message.to_string() }
+}
+
+fn parse_overflow_policy(key: &str, value: &str) -> Result<OverflowPolicy, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "block" => Ok(OverflowPolicy::Block),
+        "drop_newest" => Ok(OverflowPolicy::DropNewest),
+        "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+        "shed_by_level" => Ok(OverflowPolicy::ShedByLevel),
+        _ => Err(invalid(key, value, "expected block, drop_newest, drop_oldest or shed_by_level")),
+    }
+}
+
+// A table being read; keys are removed as they are used, whatever is left over is unknown
+struct Section {
+    prefix: String,  // "" for the root table, "file." for [file], ...
+    table: Table,
+}
+
+impl Section {
+    fn new(name: &str, table: &Table) -> Self {
+        let prefix = if name.is_empty() { String::new() } else { format!("{}.", name) };
+        Section { prefix, table: table.clone() }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    fn wrong_type(&self, name: &str, value: &Value, expected: &str) -> ConfigError {
+        invalid(&self.key(name), &value.to_string(), &format!("expected {}, found {}", expected, value.type_str()))
+    }
+
+    fn string(&mut self, name: &str) -> Result<Option<String>, ConfigError> {
+        match self.table.remove(name) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(value) => Err(self.wrong_type(name, &value, "a string")),
+        }
+    }
+
+    fn bool(&mut self, name: &str) -> Result<Option<bool>, ConfigError> {
+        match self.table.remove(name) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(value)),
+            Some(Value::String(value)) => parse_bool(&self.key(name), &value).map(Some),
+            Some(value) => Err(self.wrong_type(name, &value, "a boolean")),
+        }
+    }
+
+    fn integer(&mut self, name: &str) -> Result<Option<u64>, ConfigError> {
+        match self.table.remove(name) {
+            None => Ok(None),
+            Some(Value::Integer(value)) if value >= 0 => Ok(Some(value as u64)),
+            Some(value) => Err(self.wrong_type(name, &value, "a non-negative integer")),
+        }
+    }
+
+    // Bytes as an integer, or a string with a unit ("10MB"); 0 is no limit
+    fn size(&mut self, name: &str) -> Result<Option<Option<u64>>, ConfigError> {
+        match self.table.remove(name) {
+            None => Ok(None),
+            Some(Value::Integer(value)) if value >= 0 => Ok(Some(if value == 0 { None } else { Some(value as u64) })),
+            Some(Value::String(value)) => parse_size(&self.key(name), &value).map(Some),
+            Some(value) => Err(self.wrong_type(name, &value, "a size")),
+        }
+    }
+
+    fn section(&mut self, name: &str) -> Result<Option<Section>, ConfigError> {
+        match self.table.remove(name) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Section::new(&self.key(name), &table))),
+            Some(value) => Err(self.wrong_type(name, &value, "a table")),
+        }
+    }
+
+    fn finish(self) -> Result<(), ConfigError> {
+        match self.table.keys().next() {
+            Some(name) => Err(invalid(&self.key(name), "", "unknown setting")),
+            None => Ok(()),
+        }
+    }
+}
+
+
+// Handle for a running config watcher; dropping it stops the polling thread
+pub struct ConfigWatcher {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        let (stopped, cv) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        cv.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Logger {
+    // Polls `path` every `poll_interval` and, when its mtime changes, applies the file's `level` and
+    // `filter` to this logger. Other settings need a restart. Each applied change is logged (whatever
+    // the filter); an invalid file is reported and the current settings are kept. As at startup, MLOG_FILTER
+    // takes precedence over the file's `filter`. Stops when the handle is dropped or the logger goes away.
+    pub fn watch_config(self: &Arc<Self>, path: impl Into<PathBuf>, poll_interval: Duration) -> io::Result<ConfigWatcher> {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let logger = Arc::downgrade(self);
+        let path = path.into();
+        let last_modified = modified(&path);  // Taken here so edits made right after this call are seen
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name("mlog-config-watcher".to_string())
+            .spawn(move || watch(logger, path, last_modified, poll_interval, thread_stop))?;
+        Ok(ConfigWatcher { stop, thread: Some(thread) })
+    }
+}
+
+// Watches the config for the logger installed by `mlog::init`, Ok(None) when there is none
+pub fn watch_config(path: impl Into<PathBuf>, poll_interval: Duration) -> io::Result<Option<ConfigWatcher>> {
+    crate::global_logger().map(|logger| logger.watch_config(path, poll_interval)).transpose()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn watch(
+    logger: Weak<Logger>,
+    path: PathBuf,
+    mut last_modified: Option<SystemTime>,
+    poll_interval: Duration,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+) {
+    let (stopped, cv) = &*stop;
+    loop {
+        let guard = stopped.lock().unwrap();
+        let (guard, _) = cv.wait_timeout_while(guard, poll_interval, |stopped| !*stopped).unwrap();
+        if *guard {
+            return;
+        }
+        drop(guard);
+
+        let Some(logger) = logger.upgrade() else {
+            return;
+        };
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        reload(&logger, &path);
+    }
+}
+
+fn reload(logger: &Logger, path: &Path) {
+    let config = match LogConfig::from_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            let message = format!("Ignoring invalid log config, keeping the current settings: {}", e);
+            logger.log_unfiltered(LogRecord::new(LogLevel::Error, "mlog", message, CONSOLE_COLOR_PINK));
+            return;
+        }
+    };
+
+    // The same precedence as at startup: MLOG_FILTER replaces the file's filter (validated by from_file)
+    let (filter, directives) = match Logger::env_filter(config.log_level) {
+        Some((directives, filter)) => (filter, format!("{} (from {}, the file's filter is not used)", directives, FILTER_ENV)),
+        None => match config.filter {
+            Some(ref directives) => (TargetFilter::parse(directives, config.log_level).expect("validated filter"), directives.clone()),
+            None => (TargetFilter::new(config.log_level), "none".to_string()),
+        },
+    };
+    if filter == logger.filter() {
+        return;
+    }
+    logger.set_filter(filter);
+    let message = format!("Applied log config from {}: level {}, filter {}", path.display(), config.log_level, directives);
+    logger.log_unfiltered(LogRecord::new(LogLevel::Info, "mlog", message, CONSOLE_COLOR_INFO));
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+use std::io;
+
+use crate::ConfigError;
+
+// Why `Logger::try_new` / `mlog::try_init` could not set up logging
+#[derive(Debug)]
+pub enum MlogError {
+    Io { context: String, source: io::Error },  // e.g. the log file cannot be opened or written
+    InvalidConfig(ConfigError),                 // a setting that cannot be used, e.g. a bad pattern or filter
+    AlreadyInitialized,                         // `try_init` was called while a global logger is installed
+}
+
+impl MlogError {
+    pub(crate) fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> MlogError {
+        let context = context.into();
+        move |source| MlogError::Io { context, source }
+    }
+}
+
+impl fmt::Display for MlogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MlogError::Io { context, source } => write!(f, "{}: {}", context, source),
+            MlogError::InvalidConfig(e) => e.fmt(f),
+            MlogError::AlreadyInitialized => f.write_str("logger is already initialized"),
+        }
+    }
+}
+
+impl std::error::Error for MlogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MlogError::Io { source, .. } => Some(source),
+            MlogError::InvalidConfig(e) => Some(e),
+            MlogError::AlreadyInitialized => None,
+        }
+    }
+}
+
+impl From<ConfigError> for MlogError {
+    fn from(e: ConfigError) -> Self {
+        MlogError::InvalidConfig(e)
+    }
+}
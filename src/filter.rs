@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::{LevelSet, LogLevel};
+
+// Environment variable read by `Logger::new`, overriding `LogConfig::filter` when set
+pub const FILTER_ENV: &str = "MLOG_FILTER";
+
+
+// Error for directives that cannot be parsed, e.g. `myapp::net=loud`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterError {
+    pub directives: String,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log filter {:?}: {}", self.directives, self.message)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+
+// Per-target levels, parsed from RUST_LOG-style directives: `"info,myapp::net=warn,myapp::db=crit"`.
+//
+// A bare level sets the default, `target=level` applies to that target and everything below it
+// (`myapp::net` covers `myapp::net::tcp`, but not `myapp::network`). The longest matching target
+// wins. Levels are trace, debug, info, success, warn, error, crit or off, in any case, or a
+// LevelSet expression such as `crit+success` or `all-success`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetFilter {
+    default: u8,               // level bits for targets without a rule
+    rules: Vec<(String, u8)>,  // sorted longest target first, so the first match is the most specific
+    max_mask: u8,              // union of all rules' bits, for a quick reject
+}
+
+impl TargetFilter {
+    // Everything at `level` or more severe, for every target
+    pub fn new(level: LogLevel) -> Self {
+        TargetFilter { default: level as u8, rules: Vec::new(), max_mask: level as u8 }
+    }
+
+    // `default` applies when the directives contain no bare level
+    pub fn parse(directives: &str, default: LogLevel) -> Result<Self, FilterError> {
+        let error = |message: String| FilterError { directives: directives.to_string(), message };
+
+        let mut filter = TargetFilter::new(default);
+        for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(error(format!("missing target in {:?}", directive)));
+                    }
+                    let mask = LevelSet::parse(level).map_err(error)?.bits();
+                    // A later directive for the same target replaces the earlier one
+                    filter.rules.retain(|(existing, _)| existing != target);
+                    filter.rules.push((target.to_string(), mask));
+                }
+                None => {
+                    filter.default = LevelSet::parse(directive).map_err(error)?.bits();
+                }
+            }
+        }
+
+        filter.rules.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter.update_max_mask();
+        Ok(filter)
+    }
+
+    // Level for targets without a rule; None when the directives turned them off or selected a set
+    // that is not a threshold (see `default_levels`)
+    pub fn default_level(&self) -> Option<LogLevel> {
+        self.default_levels().threshold()
+    }
+
+    // The exact set behind `default_level`, e.g. only Crit and Success
+    pub fn default_levels(&self) -> LevelSet {
+        LevelSet::from_bits(self.default)
+    }
+
+    // Replaces the default level, keeping the per-target rules
+    pub fn set_default_level(&mut self, level: LogLevel) {
+        self.set_default_levels(LevelSet::at_least(level));
+    }
+
+    pub fn set_default_levels(&mut self, levels: LevelSet) {
+        self.default = levels.bits();
+        self.update_max_mask();
+    }
+
+    fn update_max_mask(&mut self) {
+        self.max_mask = self.rules.iter().fold(self.default, |mask, (_, rule)| mask | *rule);
+    }
+
+    // Whether `level` could be enabled for some target; a cheap check before the record is built
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        self.max_mask & level.bit() != 0
+    }
+
+    pub(crate) fn max_mask(&self) -> u8 {
+        self.max_mask
+    }
+
+    pub(crate) fn default_mask(&self) -> u8 {
+        self.default
+    }
+
+    pub(crate) fn has_rules(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    // Most verbose level enabled for any target, None when everything is off
+    pub fn max_level(&self) -> Option<LogLevel> {
+        level_for_mask(self.max_mask)
+    }
+
+    pub fn enabled_for(&self, level: LogLevel, target: &str) -> bool {
+        if !self.enabled(level) {
+            return false;
+        }
+        self.mask_for(target) & level.bit() != 0
+    }
+
+    fn mask_for(&self, target: &str) -> u8 {
+        self.rule_mask(target).unwrap_or(self.default)
+    }
+
+    // Mask of the most specific rule covering `target`, if any
+    pub(crate) fn rule_mask(&self, target: &str) -> Option<u8> {
+        self.rules
+            .iter()
+            .find(|(prefix, _)| covers(prefix, target))
+            .map(|(_, mask)| *mask)
+    }
+}
+
+// `prefix` is the target itself or one of its parent modules
+fn covers(prefix: &str, target: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+// Most verbose level a mask lets through
+fn level_for_mask(mask: u8) -> Option<LogLevel> {
+    LevelSet::from_bits(mask).min_level()
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::SinkId;
+
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;                            // retries before a record is given up
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(10);  // wait before the first retry, doubled each time
+pub(crate) const RETRY_RECHECK_INTERVAL: Duration = Duration::from_secs(1);  // while a sink stays down, one retry per interval
+
+// What the logger does when a sink fails to write or flush, e.g. on a full disk or a deleted log
+// directory. Logging never panics on these errors; every failure is counted in `Logger::io_error_count`.
+//
+// Retries only go all the way on the first failure; while the sink keeps failing, one record per
+// second is retried and the others are dropped at once. The callback runs on the writing thread
+// with the sink locked: it may log (that record skips the failing sink) but must not wait for
+// another thread that logs.
+#[derive(Clone, Default)]
+pub enum IoErrorPolicy {
+    Ignore,   // Drop the record
+    #[default]
+    Stderr,   // Write the record to stderr instead, with a note when the sink starts and stops failing
+    Retry { attempts: u32, backoff: Duration },  // Try again up to `attempts` times, doubling the wait from `backoff`, then drop the record
+    Callback(Arc<dyn Fn(&SinkError<'_>) + Send + Sync>),  // Hand every failure to the application
+}
+
+impl IoErrorPolicy {
+    // Retry with the default 3 attempts, 10 ms apart at first
+    pub fn retry() -> Self {
+        IoErrorPolicy::Retry { attempts: DEFAULT_RETRY_ATTEMPTS, backoff: DEFAULT_RETRY_BACKOFF }
+    }
+
+    pub fn callback<F: Fn(&SinkError<'_>) + Send + Sync + 'static>(callback: F) -> Self {
+        IoErrorPolicy::Callback(Arc::new(callback))
+    }
+}
+
+impl fmt::Debug for IoErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoErrorPolicy::Ignore => f.write_str("Ignore"),
+            IoErrorPolicy::Stderr => f.write_str("Stderr"),
+            IoErrorPolicy::Retry { attempts, backoff } => {
+                f.debug_struct("Retry").field("attempts", attempts).field("backoff", backoff).finish()
+            }
+            IoErrorPolicy::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+// The sink call that failed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SinkOperation {
+    Write,
+    Flush,
+    SessionEnd,
+}
+
+impl fmt::Display for SinkOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SinkOperation::Write => "write",
+            SinkOperation::Flush => "flush",
+            SinkOperation::SessionEnd => "session end",
+        })
+    }
+}
+
+// A failure passed to `IoErrorPolicy::Callback`
+#[derive(Debug)]
+pub struct SinkError<'a> {
+    pub sink: SinkId,
+    pub operation: SinkOperation,
+    pub error: &'a io::Error,
+    pub line: Option<&'a str>,  // The formatted record that was not written, for failed writes
+}
+
+impl fmt::Display for SinkError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "log sink {} {} failed: {}", self.sink.0, self.operation, self.error)
+    }
+}
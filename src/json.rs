@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use chrono::SecondsFormat;
+
+use crate::color::strip_ansi;
+use crate::sink::Formatter;
+use crate::{LogRecord, Value};
+
+// JSON Lines formatter: one object per record, for log indexers. Keys, in order:
+// timestamp (RFC 3339), level, app, message, thread, target, module_path, file, line, fields.
+// The source location keys are left out when unknown, `fields` when the record has none.
+#[derive(Clone, Debug)]
+pub struct JsonFormatter {
+    application_name: String,
+}
+
+impl JsonFormatter {
+    pub fn new(application_name: &str) -> Self {
+        JsonFormatter { application_name: application_name.to_string() }
+    }
+}
+
+impl Formatter for JsonFormatter {
+    fn format(&self, record: &LogRecord) -> String {
+        let mut out = String::with_capacity(128 + record.message.len());
+
+        out.push_str("{\"timestamp\":");
+        write_json_string(&mut out, &record.time.to_rfc3339_opts(SecondsFormat::Millis, false));
+        out.push_str(",\"level\":");
+        write_json_string(&mut out, record.level.as_str());
+        out.push_str(",\"app\":");
+        write_json_string(&mut out, &self.application_name);
+        out.push_str(",\"message\":");
+        write_json_string(&mut out, &strip_ansi(&record.message));
+        out.push_str(",\"thread\":");
+        write_json_string(&mut out, &record.thread);
+        out.push_str(",\"target\":");
+        write_json_string(&mut out, &record.target);
+        if let Some(ref module_path) = record.module_path {
+            out.push_str(",\"module_path\":");
+            write_json_string(&mut out, module_path);
+        }
+        if let Some(ref file) = record.file {
+            out.push_str(",\"file\":");
+            write_json_string(&mut out, file);
+        }
+        if let Some(line) = record.line {
+            let _ = write!(out, ",\"line\":{}", line);
+        }
+
+        if !record.fields.is_empty() {
+            out.push_str(",\"fields\":{");
+            for (i, (key, value)) in record.fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(&mut out, key);
+                out.push(':');
+                write_json_value(&mut out, value);
+            }
+            out.push('}');
+        }
+
+        out.push('}');
+        out
+    }
+}
+
+fn write_json_value(out: &mut String, value: &Value) {
+    let _ = match value {
+        Value::Bool(v) => write!(out, "{}", v),
+        Value::I64(v) => write!(out, "{}", v),
+        Value::U64(v) => write!(out, "{}", v),
+        Value::F64(v) if v.is_finite() => write!(out, "{}", v),
+        Value::F64(_) => write!(out, "null"),  // JSON has no NaN or infinity
+        Value::Str(v) => {
+            write_json_string(out, v);
+            Ok(())
+        }
+    };
+}
+
+// Quoted JSON string. Quotes, backslashes and every control character are escaped, so the
+// output never spans more than one line.
+pub(crate) fn write_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,264 @@
+use std::fmt::{self, Write};
+
+use crate::sink::Formatter;
+use crate::value::write_fields;
+use crate::LogRecord;
+
+pub const DEFAULT_PATTERN: &str = "[{time}] {level:<7} {msg}";
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+
+// Error for a pattern that cannot be parsed, e.g. `{levle}` or `{msg:>x}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternError {
+    pub pattern: String,
+    pub message: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log pattern {:?}: {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+
+// Formatter driven by a pattern string such as `"{time} {level:>5} [{thread}] {target}: {msg}"`.
+//
+// Placeholders: time, level, thread, thread_id, target, module, file, line, msg, fields. Each takes an optional `:spec` with the same
+// fill/align/width/precision syntax as `format!`: `{level:>5}`, `{thread:-^12}`, `{msg:.80}`.
+// `{{` and `}}` are literal braces. Structured fields are rendered as `key=value` after the
+// message, unless the pattern places them itself with `{fields}`.
+#[derive(Clone, Debug)]
+pub struct PatternFormatter {
+    segments: Vec<Segment>,
+    time_format: String,
+    fields_in_msg: bool,  // No `{fields}` placeholder, so `{msg}` carries the fields
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Field(Field, Spec),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Field {
+    Time,
+    Level,
+    Thread,
+    ThreadId,
+    Target,
+    Module,
+    File,
+    Line,
+    Message,
+    Fields,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Spec {
+    fill: char,
+    align: Align,
+    width: usize,
+    precision: Option<usize>,  // max chars, longer values are truncated
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Spec { fill: ' ', align: Align::Left, width: 0, precision: None }
+    }
+}
+
+impl PatternFormatter {
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let error = |message: String| PatternError { pattern: pattern.to_string(), message };
+
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(error("unclosed '{'".to_string())),
+                        }
+                    }
+
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name, Some(spec)),
+                        None => (placeholder.as_str(), None),
+                    };
+                    let field = parse_field(name).ok_or_else(|| error(format!("unknown placeholder {{{}}}", name)))?;
+                    let spec = match spec {
+                        Some(spec) => parse_spec(spec).ok_or_else(|| error(format!("invalid format spec {:?}", spec)))?,
+                        None => Spec::default(),
+                    };
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(field, spec));
+                }
+                '}' => return Err(error("unmatched '}', use '}}' for a literal brace".to_string())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let fields_in_msg = !segments.iter().any(|segment| matches!(segment, Segment::Field(Field::Fields, _)));
+        Ok(PatternFormatter { segments, time_format: DEFAULT_TIME_FORMAT.to_string(), fields_in_msg })
+    }
+
+    // strftime-style format used for `{time}`
+    pub fn with_time_format(mut self, time_format: &str) -> Self {
+        self.time_format = time_format.to_string();
+        self
+    }
+}
+
+impl Default for PatternFormatter {
+    fn default() -> Self {
+        PatternFormatter::new(DEFAULT_PATTERN).expect("default pattern is valid")
+    }
+}
+
+impl Formatter for PatternFormatter {
+    fn format(&self, record: &LogRecord) -> String {
+        let mut out = String::with_capacity(64 + record.message.len());
+        let mut value = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field(field, spec) => {
+                    value.clear();
+                    let _ = match field {
+                        Field::Time => write!(value, "{}", record.time.format(&self.time_format)),
+                        Field::Level => write!(value, "{}", record.level),
+                        Field::Thread => write!(value, "{}", record.thread),
+                        Field::ThreadId => write!(value, "{:?}", record.thread_id),
+                        Field::Target => write!(value, "{}", record.target),
+                        Field::Module => write!(value, "{}", record.module_path.as_deref().unwrap_or("")),
+                        Field::File => write!(value, "{}", record.file.as_deref().unwrap_or("")),
+                        Field::Line => match record.line {
+                            Some(line) => write!(value, "{}", line),
+                            None => Ok(()),
+                        },
+                        Field::Message => {
+                            value.push_str(&record.message);
+                            if self.fields_in_msg && !record.fields.is_empty() {
+                                value.push(' ');
+                                write_fields(&mut value, &record.fields);
+                            }
+                            Ok(())
+                        }
+                        Field::Fields => {
+                            write_fields(&mut value, &record.fields);
+                            Ok(())
+                        }
+                    };
+                    pad(&mut out, &value, spec);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parse_field(name: &str) -> Option<Field> {
+    match name.trim() {
+        "time" => Some(Field::Time),
+        "level" => Some(Field::Level),
+        "thread" => Some(Field::Thread),
+        "thread_id" => Some(Field::ThreadId),
+        "target" => Some(Field::Target),
+        "module" => Some(Field::Module),
+        "file" => Some(Field::File),
+        "line" => Some(Field::Line),
+        "msg" | "message" => Some(Field::Message),
+        "fields" => Some(Field::Fields),
+        _ => None,
+    }
+}
+
+// [[fill]align][width][.precision]
+fn parse_spec(spec: &str) -> Option<Spec> {
+    let parse_align = |c: char| match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+
+    let mut result = Spec::default();
+    let chars: Vec<char> = spec.chars().collect();
+    let mut rest = &chars[..];
+
+    if let Some(align) = chars.get(1).copied().and_then(parse_align) {
+        result.fill = chars[0];
+        result.align = align;
+        rest = &chars[2..];
+    } else if let Some(align) = chars.first().copied().and_then(parse_align) {
+        result.align = align;
+        rest = &chars[1..];
+    }
+
+    let rest: String = rest.iter().collect();
+    let (width, precision) = match rest.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (rest.as_str(), None),
+    };
+    if !width.is_empty() {
+        result.width = width.parse().ok()?;
+    }
+    if let Some(precision) = precision {
+        result.precision = Some(precision.parse().ok()?);
+    }
+    Some(result)
+}
+
+fn pad(out: &mut String, value: &str, spec: &Spec) {
+    let value = match spec.precision {
+        Some(max) => match value.char_indices().nth(max) {
+            Some((end, _)) => &value[..end],
+            None => value,
+        },
+        None => value,
+    };
+
+    let len = value.chars().count();
+    let padding = spec.width.saturating_sub(len);
+    let (before, after) = match spec.align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+
+    out.extend(std::iter::repeat_n(spec.fill, before));
+    out.push_str(value);
+    out.extend(std::iter::repeat_n(spec.fill, after));
+}
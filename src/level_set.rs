@@ -0,0 +1,151 @@
+use std::fmt;
+
+use crate::LogLevel;
+
+// LogLevel values are cumulative masks (Warn = 0b0000111 is Crit, Error and Warn), so every level
+// owns one bit (`LogLevel::bit`) and any set of levels is an OR of those bits. A threshold is just
+// the level's own mask, while exact sets such as "only Crit and Success" use the bits directly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LevelSet(u8);
+
+const LEVELS: [LogLevel; 7] = [
+    LogLevel::Crit,
+    LogLevel::Error,
+    LogLevel::Warn,
+    LogLevel::Success,
+    LogLevel::Info,
+    LogLevel::Debug,
+    LogLevel::Trace,
+];
+
+impl LevelSet {
+    pub const ALL: LevelSet = LevelSet(LogLevel::Trace as u8);
+    pub const NONE: LevelSet = LevelSet(0);
+
+    // `level` and everything more severe, the same as a `log_level` threshold
+    pub const fn at_least(level: LogLevel) -> Self {
+        LevelSet(level as u8)
+    }
+
+    pub fn only(levels: &[LogLevel]) -> Self {
+        levels.iter().fold(LevelSet::NONE, |set, level| set.with(*level))
+    }
+
+    pub const fn with(self, level: LogLevel) -> Self {
+        LevelSet(self.0 | level.bit())
+    }
+
+    pub const fn without(self, level: LogLevel) -> Self {
+        LevelSet(self.0 & !level.bit())
+    }
+
+    pub const fn union(self, other: LevelSet) -> Self {
+        LevelSet(self.0 | other.0)
+    }
+
+    pub const fn contains(self, level: LogLevel) -> bool {
+        self.0 & level.bit() != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub(crate) const fn from_bits(bits: u8) -> Self {
+        LevelSet(bits & LevelSet::ALL.0)
+    }
+
+    // Least severe level in the set, which for a threshold is the threshold itself
+    pub fn min_level(self) -> Option<LogLevel> {
+        LEVELS.iter().rev().copied().find(|level| self.contains(*level))
+    }
+
+    // The level this set is the threshold of, None for any other set (e.g. only Crit and Success)
+    pub fn threshold(self) -> Option<LogLevel> {
+        self.min_level().filter(|level| self.0 == *level as u8)
+    }
+
+    // Levels in the set, most severe first
+    pub fn levels(self) -> impl Iterator<Item = LogLevel> {
+        LEVELS.into_iter().filter(move |level| self.contains(*level))
+    }
+
+    // Parses a set expression: a level name on its own is a threshold (`warn` = Warn and above),
+    // `+level` adds and `-level` removes a single level, and `all` / `none` (or `off`) are the full
+    // and empty sets. `crit+success` is only Crit and Success, `all-success` everything but Success.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let start = text.find(['+', '-']).unwrap_or(text.len());
+        let (first, mut rest) = text.split_at(start);
+
+        let mut set = match first.trim().to_ascii_lowercase().as_str() {
+            "" => return Err(format!("missing level in {:?}", text)),
+            "all" => LevelSet::ALL,
+            "none" | "off" => LevelSet::NONE,
+            name => LevelSet::at_least(parse_name(name)?),
+        };
+
+        while let Some(op) = rest.chars().next() {
+            let term = &rest[1..];
+            let end = term.find(['+', '-']).unwrap_or(term.len());
+            let level = parse_name(&term[..end])?;
+            set = if op == '+' { set.with(level) } else { set.without(level) };
+            rest = &term[end..];
+        }
+        Ok(set)
+    }
+}
+
+fn parse_name(name: &str) -> Result<LogLevel, String> {
+    LogLevel::from_name(name).ok_or_else(|| format!("unknown level {:?}", name.trim()))
+}
+
+impl Default for LevelSet {
+    fn default() -> Self {
+        LevelSet::ALL
+    }
+}
+
+impl From<LogLevel> for LevelSet {
+    fn from(level: LogLevel) -> Self {
+        LevelSet::at_least(level)
+    }
+}
+
+// Written in the syntax `parse` accepts: `warn`, `crit+success`, `none`
+impl fmt::Display for LevelSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        if let Some(level) = LEVELS.iter().find(|level| LevelSet::at_least(**level) == *self) {
+            return f.write_str(&level.as_str().to_ascii_lowercase());
+        }
+        // Either the largest threshold inside the set plus the remaining levels, or the smallest
+        // threshold around it minus the missing ones, whichever needs fewer terms
+        let below = LEVELS.iter().rev().copied().find(|level| self.0 & *level as u8 == *level as u8);
+        let above = LEVELS.iter().copied().find(|level| *level as u8 & self.0 == self.0).unwrap_or(LogLevel::Trace);
+        let extra = LevelSet(self.0 & !below.map_or(0, |level| level as u8));
+        let missing = LevelSet(above as u8 & !self.0);
+
+        if extra.0.count_ones() <= missing.0.count_ones() {
+            match below {
+                Some(level) => f.write_str(&level.as_str().to_ascii_lowercase())?,
+                None => f.write_str("none")?,
+            }
+            for level in extra.levels() {
+                write!(f, "+{}", level.as_str().to_ascii_lowercase())?;
+            }
+        } else {
+            f.write_str(&above.as_str().to_ascii_lowercase())?;
+            for level in missing.levels() {
+                write!(f, "-{}", level.as_str().to_ascii_lowercase())?;
+            }
+        }
+        Ok(())
+    }
+}
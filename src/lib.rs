@@ -1,15 +1,60 @@
-use std::sync::atomic::{AtomicUsize, AtomicPtr, AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::{thread, ptr, fs};
-use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, TryLockError, Weak};
+use std::{fmt, io, thread};
+use std::thread::{JoinHandle, ThreadId};
+use std::time::Instant;
 use std::time::Duration;
-use std::{io::BufWriter, io::Write};
-use chrono::Local;
+use chrono::{DateTime, Local};
 use std::panic::{self};
-use std::fs::{File, OpenOptions};
 
-const BUFFER_CAPACITY: usize = 15;  
-const MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;  // 10 MB max log file size before rotation to new file
+mod color;
+mod compress;
+mod config;
+#[cfg(feature = "toml")]
+mod config_file;
+mod error;
+mod filter;
+mod io_error;
+mod json;
+mod layout;
+mod level_set;
+mod logfmt;
+mod path_template;
+#[cfg(feature = "log")]
+mod log_bridge;
+mod queue;
+mod rotation;
+mod sink;
+#[cfg(feature = "tracing")]
+mod tracing_layer;
+mod value;
+use queue::BoundedQueue;
+pub use color::{strip_ansi, ColorChoice, ColorDepth};
+pub use compress::Compression;
+#[cfg(feature = "toml")]
+pub use config_file::{watch_config, ConfigWatcher};
+pub use error::MlogError;
+pub use config::{ConfigError, ENV_APP, ENV_ASYNC, ENV_COLOR, ENV_CONSOLE, ENV_CONSOLE_LEVELS, ENV_FILE, ENV_FILE_FORMAT, ENV_FILE_LEVELS, ENV_FILE_COMPRESS, ENV_FILE_KEEP_AGE, ENV_FILE_KEEP_FILES, ENV_FILE_KEEP_SIZE, ENV_FILE_LINK, ENV_FILE_MAX_SIZE, ENV_FILE_PER_SESSION, ENV_FILE_ROTATE, ENV_LEVEL, ENV_ON_IO_ERROR, ENV_TIME_FORMAT};
+pub use filter::{FilterError, TargetFilter, FILTER_ENV};
+pub use io_error::{IoErrorPolicy, SinkError, SinkOperation};
+use io_error::RETRY_RECHECK_INTERVAL;
+pub use json::JsonFormatter;
+pub use layout::{PatternError, PatternFormatter, DEFAULT_PATTERN};
+pub use level_set::LevelSet;
+pub use logfmt::LogfmtFormatter;
+pub use path_template::expand_path;
+pub use rotation::{RetentionPolicy, RotationInterval, RotationPolicy, DEFAULT_MAX_FILE_SIZE};
+pub use sink::{ConsoleSink, FileSink, Formatter, Sink, SinkConfig, SinkId, WriterSink};
+#[cfg(feature = "tracing")]
+pub use tracing_layer::MlogLayer;
+pub use value::Value;
+
+const DEFAULT_BUFFER_CAPACITY: usize = 1024;  // records held by the async queue before it is considered full
+pub const MAX_BUFFER_CAPACITY: usize = 1 << 20;  // largest accepted buffer_capacity, the queue is allocated up front
+const DEFAULT_MAX_BATCH_SIZE: usize = 64;     // records written per wake-up of the async writer
+const DEFAULT_MAX_LATENCY: Duration = Duration::from_millis(100);  // longest a record waits in the queue
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);  // how long shutdown waits for the writer to drain
 
 pub const CONSOLE_COLOR_WHITE: &str = "\x1b[37m";
 pub const CONSOLE_COLOR_INFO: &str = "\x1b[38;2;127;156;255m";
@@ -22,322 +67,1141 @@ pub const CONSOLE_BG_COLOR_RED: &str = "\x1b[41m";
 pub const CONSOLE_BG_COLOR_GREEN: &str = "\x1b[42m";
 pub const CONSOLE_COLOR_RESET: &str = "\x1b[0m";
 pub const CONSOLE_COLOR_GREEN: &str = "\x1b[1;32m"; 
-pub const CONSOLE_COLOR_MAGENTA: &str = "\x1b[35m";    
+pub const CONSOLE_COLOR_MAGENTA: &str = "\x1b[35m";
+pub const CONSOLE_COLOR_DEBUG: &str = "\x1b[38;2;160;160;180m";
+pub const CONSOLE_COLOR_TRACE: &str = "\x1b[38;2;110;110;120m";    
 
 
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 pub enum LogLevel {
-    Info = 0b11111,    // Everything
+    Trace = 0b1111111, // Everything
+    Debug = 0b0111111, // Everything but Trace
+    Info = 0b0011111,  // Everything but Debug and Trace
     Success = 0b01111, // Crit, Error, Warn, and Success messages
     Warn = 0b00111,    // Crit, Error, and Warn messages
     Error = 0b00011,   // Crit and Error messages
     Crit = 0b00001,    // Only Crit messages
 }
 
+impl LogLevel {
+    // Label used in text output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Success => "SUCCESS",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Crit => "CRIT",
+        }
+    }
+
+    // The single bit of the mask that belongs to this level (Crit = 1, Error = 2, ... Trace = 64),
+    // used to test a level against a LevelSet or a filter mask
+    pub const fn bit(self) -> u8 {
+        (self as u8 >> 1) + 1
+    }
+
+    // Case-insensitive inverse of `as_str`, also accepting "warning" and "critical"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "trace" => Some(LogLevel::Trace),
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "success" => Some(LogLevel::Success),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            "crit" | "critical" => Some(LogLevel::Crit),
+            _ => None,
+        }
+    }
+
+    // Console color the logging macros use for this level
+    pub fn color(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => CONSOLE_COLOR_TRACE,
+            LogLevel::Debug => CONSOLE_COLOR_DEBUG,
+            LogLevel::Info => CONSOLE_COLOR_INFO,
+            LogLevel::Success => CONSOLE_BG_COLOR_SUCCESS,
+            LogLevel::Warn => CONSOLE_COLOR_WARN,
+            LogLevel::Error => CONSOLE_COLOR_PINK,
+            LogLevel::Crit => CONSOLE_BG_COLOR_RED,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())  // honours width/alignment, e.g. `{:>7}`
+    }
+}
+
+
+// A single log event, as queued in async mode and handed to formatters and sinks
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub time: DateTime<Local>,
+    pub message: String,
+    pub target: String,       // Where the record comes from, the module path for the logging macros
+    pub module_path: Option<String>,  // Callsite, when known
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub thread: String,       // Name (or id) of the thread that logged it
+    pub thread_id: ThreadId,
+    pub color: &'static str,  // Console color the record was logged with
+    pub fields: Vec<(String, Value)>,  // Structured key/value pairs, in the order they were logged
+}
+
+impl LogRecord {
+    // Stamps the record with the current time and thread
+    pub fn new(level: LogLevel, target: &str, message: String, color: &'static str) -> Self {
+        let current = thread::current();
+        let thread = match current.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", current.id()),
+        };
+
+        LogRecord {
+            level,
+            time: Local::now(),
+            message,
+            target: target.to_string(),
+            module_path: None,
+            file: None,
+            line: None,
+            thread,
+            thread_id: current.id(),
+            color,
+            fields: Vec::new(),
+        }
+    }
+
+    // Where the record was logged, filled in by the logging macros from module_path!/file!/line!
+    pub fn with_callsite(mut self, module_path: &str, file: &str, line: u32) -> Self {
+        self.module_path = Some(module_path.to_string());
+        self.file = Some(file.to_string());
+        self.line = Some(line);
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Vec<(String, Value)>) -> Self {
+        self.fields = fields;
+        self
+    }
+}
+
+
+// What the async logger does with a new record when its queue is full
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    Block,        // Wait until the queue has room (the logging thread drains it if needed)
+    DropNewest,   // Discard the incoming record
+    DropOldest,   // Discard the oldest queued record to make room for the incoming one
+    ShedByLevel,  // Lower levels start dropping before the queue is full, Crit is never dropped
+}
+
+// How the log file is written
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,    // Lines laid out by `file_pattern`, with session banners
+    Json,    // JSON Lines (see JsonFormatter), session start/end written as records
+    Logfmt,  // `ts=... level=... app=... msg="..."` lines (see LogfmtFormatter), session start/end as records
+}
+
 
 pub struct LogConfig {
     pub log_level: LogLevel,
+    pub filter: Option<String>,       // Per-target directives, e.g. "info,myapp::net=warn" (MLOG_FILTER overrides)
     pub application_name: String,
-    pub log_filepath: Option<String>,   // Optional log file path
+    pub log_filepath: Option<String>,   // Optional log file path, a template such as "logs/{app}/{date}.log"
+    pub file_per_session: bool,        // Start every session with an empty file, rotating out the old one
+    pub current_link: Option<String>,  // Symlink kept pointing at the active log file, e.g. "logs/current.log"
     pub console_flag: bool,            // Flag to log to console
+    pub console_levels: LevelSet,      // Levels written to the console, on top of log_level / filter
+    pub color: ColorChoice,           // Console colors: Always, Never, or Auto (terminal detection)
     pub async_flag: bool,             // Flag to enable async logging
     pub multi_threaded_flag: bool,   // Flag for multi-threaded mode
     pub time_format: String,        // Time format string
+    pub console_pattern: Option<String>,  // Layout of console lines (default: DEFAULT_PATTERN)
+    pub file_pattern: Option<String>,    // Layout of log file lines (default: DEFAULT_PATTERN)
+    pub file_format: OutputFormat,      // Text lines, JSON Lines or logfmt in the log file
+    pub file_levels: LevelSet,         // Levels written to the log file, on top of log_level / filter
+    pub rotation: RotationPolicy,      // When the log file is rotated: by size, on a schedule, or both
+    pub compression: Compression,      // Compression of rotated files (features "gzip" / "zstd")
+    pub retention: RetentionPolicy,    // How many / how much / how old rotated files are kept
+    pub buffer_capacity: usize,    // Async queue capacity (rounded up to a power of two), 1 to MAX_BUFFER_CAPACITY
+    pub overflow_policy: OverflowPolicy,  // What to do when the async queue is full
+    pub max_batch_size: usize,          // Async writer wakes once this many records are queued
+    pub max_latency: Duration,         // ... or once the oldest record has waited this long
+    pub shutdown_timeout: Duration,   // How long shutdown waits for the async writer to drain and exit
+    pub io_error_policy: IoErrorPolicy,  // What to do when a sink fails to write, e.g. on a full disk
+    pub sinks: Vec<SinkConfig>,      // Extra outputs, on top of console_flag / log_filepath
 }
 
 
 impl Default for LogConfig {
     fn default() -> Self {
         LogConfig {
-            log_level: LogLevel::Info,                 // Default to logging Everything
+            log_level: LogLevel::Info,                 // Default to logging everything but Debug and Trace
+            filter: None,                             // Same level for every target
             application_name: "default application".to_string(),  // Default program name
             log_filepath: None,                      // No log file by default
+            file_per_session: false,                // Keep appending to the same file
+            current_link: None,                    // No symlink
             console_flag: true,                     // Log to console by default
+            console_levels: LevelSet::ALL,         // Every level the filter lets through
+            color: ColorChoice::Auto,              // Color when stdout is a terminal
             async_flag: false,                     // No async by default
             multi_threaded_flag: false,           // Single-threaded by default
             time_format: "%Y-%m-%d %H:%M:%S".to_string(),  // Default time format with milliseconds
+            console_pattern: None,                   // "[{time}] {level:<7} {msg}"
+            file_pattern: None,                     // "[{time}] {level:<7} {msg}"
+            file_format: OutputFormat::Text,       // Plain text log file
+            file_levels: LevelSet::ALL,           // Every level the filter lets through
+            rotation: RotationPolicy::default(),  // Rotate at 10 MB
+            compression: Compression::None,      // Rotated files stay plain text
+            retention: RetentionPolicy::keep_all(),  // Never delete rotated files
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY,    // Async queue capacity
+            overflow_policy: OverflowPolicy::DropNewest,  // Drop incoming records when full
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,      // Records per async write batch
+            max_latency: DEFAULT_MAX_LATENCY,           // Upper bound on async write delay
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT, // Wait for the async writer on shutdown
+            io_error_policy: IoErrorPolicy::Stderr,    // Failed records go to stderr
+            sinks: Vec::new(),                         // No extra outputs by default
+        }
+    }
+}
+
+
+// Returned by shutdown when the async writer did not finish draining before `shutdown_timeout`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ShutdownTimeout {
+    pub pending: usize,  // Records still queued when the deadline expired
+}
+
+impl fmt::Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "logger shutdown timed out with {} records still pending", self.pending)
+    }
+}
+
+impl std::error::Error for ShutdownTimeout {}
+
+
+// State shared between the logger and its async writer thread. The thread only keeps a Weak
+// reference to the Logger itself, so dropping the last Arc<Logger> is enough to end it.
+struct WriterSignal {
+    should_run: AtomicBool,  // Control flag for async thread
+    wake: Mutex<bool>,      // Set when the writer has work (guards writer_cv and space_cv)
+    writer_cv: Condvar,    // Wakes the writer
+    space_cv: Condvar,    // Wakes producers blocked on a full queue
+    done: Mutex<bool>,   // Set by the writer right before it exits
+    done_cv: Condvar,
+}
+
+impl WriterSignal {
+    fn wake_writer(&self) {
+        *self.wake.lock().unwrap() = true;
+        self.writer_cv.notify_one();
+    }
+
+    // Waits until the writer has exited or the deadline passes, returns whether it exited
+    fn wait_done(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            done = self.done_cv.wait_timeout(done, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+
+// A sink registered with a running logger
+struct SinkSlot {
+    id: SinkId,
+    levels: LevelSet,
+    formatter: Option<Box<dyn Formatter>>,
+    sink: Mutex<Box<dyn Sink>>,
+    health: Mutex<SinkHealth>,  // Recent failures, for the I/O error policy
+}
+
+// Only touched while holding the slot's sink lock
+#[derive(Default)]
+struct SinkHealth {
+    failing: Option<SinkOperation>,  // Call that started the current run of failures
+    retry_at: Option<Instant>,       // Retry policy: failures before this are not retried
+}
+
+impl SinkSlot {
+    // A success of the call that started the failures ends them; true if the sink was failing
+    fn succeeded(&self, operation: SinkOperation) -> bool {
+        let mut health = self.health.lock().unwrap();
+        if health.failing != Some(operation) {
+            return false;
+        }
+        *health = SinkHealth::default();
+        true
+    }
+
+    // True for the first failure of a run
+    fn failed(&self, operation: SinkOperation) -> bool {
+        let mut health = self.health.lock().unwrap();
+        let first = health.failing.is_none();
+        if first {
+            health.failing = Some(operation);
         }
+        first
+    }
+
+    // Waits for the sink, unless this thread is already writing: a sink it holds further up the
+    // stack (or one busy on another thread) is skipped then. A sink that panicked in an earlier
+    // call stays in use; the panic does not stop logging to it or to the other sinks.
+    fn lock(&self, reentrant: bool) -> Option<MutexGuard<'_, Box<dyn Sink>>> {
+        if reentrant {
+            match self.sink.try_lock() {
+                Ok(sink) => Some(sink),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            }
+        } else {
+            Some(self.sink.lock().unwrap_or_else(PoisonError::into_inner))
+        }
+    }
+}
+
+thread_local! {
+    // Set while this thread writes to sinks, holding their locks (and `drain_lock` or `mutex`). A record
+    // logged from in there, e.g. by the panic hook when a sink panics, is written straight to the sinks
+    // that are free instead of waiting for locks this thread already holds.
+    static WRITING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn reentrant() -> bool {
+    WRITING.with(Cell::get)
+}
+
+// Marks the thread as writing until dropped (also when unwinding from a panic)
+struct WritingGuard(bool);
+
+impl WritingGuard {
+    fn enter() -> Self {
+        WritingGuard(WRITING.with(|writing| writing.replace(true)))
+    }
+}
+
+impl Drop for WritingGuard {
+    fn drop(&mut self) {
+        WRITING.with(|writing| writing.set(self.0));
     }
 }
 
 
 pub struct Logger {
     pub config: LogConfig,
-    log_level_mask : u8,                                    // bitmask for log-levels
-    buffer: [UnsafeCell<Option<String>>; BUFFER_CAPACITY], // Use UnsafeCell for interior mutability
-    head: AtomicUsize,  // Atomic index for the head of the buffer (write position in async mode)
-    tail: AtomicUsize,  // Atomic index for the tail of the buffer (read/flush position in async mode)
-    should_run: Arc<AtomicBool>,  // Control flag for async thread
-    flush_interval: Duration,
+    filter: RwLock<TargetFilter>,   // log_level plus per-target directives, replaceable at runtime
+    level_mask: AtomicU8,           // Mask for targets without a rule (the filter's default level)
+    max_mask: AtomicU8,             // Most verbose mask of `filter`, checked before taking the lock
+    has_rules: AtomicBool,          // Without per-target rules the atomics alone decide
+    buffer: BoundedQueue<LogRecord>,  // Multi-producer queue of records (async mode)
+    drain_lock: Mutex<()>,        // Keeps a single consumer draining the queue at a time
+    in_flight: AtomicUsize,      // Records taken off the queue but not yet written
+    dropped_total: AtomicU64,    // Records dropped by the overflow policy since startup
+    dropped_pending: Mutex<(u64, Option<DateTime<Local>>)>,  // Drops not yet reported, and when they started
+    io_errors: AtomicU64,        // Failed sink writes and flushes since startup
+    signal: Arc<WriterSignal>,   // Wake-up and stop signalling for the async writer
+    writer_thread: Mutex<Option<JoinHandle<()>>>,  // Async writer, joined on shutdown
     mutex: Option<Mutex<()>>, // Mutex for thread-safe access when async mode is disabled
-    file_writer: Option<Mutex<BufWriter<File>>>,  // Writer for log file
+    sinks: RwLock<Vec<SinkSlot>>,          // Outputs, written in registration order
+    next_sink_id: AtomicU64,
+    default_formatter: PatternFormatter,  // Used by sinks registered without a formatter
 }
 
-unsafe impl Sync for Logger {} // Required for sharing the logger across threads when async mode is enabled
-
 impl Logger {
+    // Panics where `try_new` returns an error
     pub fn new(config: LogConfig) -> Arc<Self> {
-        // Only create the file writer if a valid log file path is provided
-        let log_file = config.log_filepath.as_ref().map(|p| {
-            let file_path = if p.ends_with(".log") {
-                p.clone()
-            } else {
-                format!("{}.log", p)
-            };
+        Logger::try_new(config).unwrap_or_else(|e| panic!("Failed to create logger: {}", e))
+    }
 
-            // Create a log file and wrap it in a Mutex for safe access
-            let file = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&file_path)
-                .expect("Failed to open log file");
-            Mutex::new(BufWriter::new(file))
-        });
+    pub fn try_new(mut config: LogConfig) -> Result<Arc<Self>, MlogError> {
+        config::parse_time_format("time_format", &config.time_format)?;
+        if !(1..=MAX_BUFFER_CAPACITY).contains(&config.buffer_capacity) {
+            return Err(ConfigError {
+                key: "buffer_capacity".to_string(),
+                value: config.buffer_capacity.to_string(),
+                message: format!("expected 1 to {}", MAX_BUFFER_CAPACITY),
+            }.into());
+        }
+
+        // Built-in console and file outputs come first, then any user sinks
+        let pattern_formatter = |key: &str, pattern: &Option<String>| -> Result<Option<PatternFormatter>, ConfigError> {
+            pattern.as_ref().map(|pattern| {
+                PatternFormatter::new(pattern)
+                    .map(|formatter| formatter.with_time_format(&config.time_format))
+                    .map_err(|e| ConfigError { key: key.to_string(), value: pattern.clone(), message: e.message })
+            }).transpose()
+        };
+
+        let mut sink_configs = Vec::new();
+        if config.console_flag {
+            let mut console = SinkConfig::new(ConsoleSink::with_color(config.color)).with_levels(config.console_levels);
+            if let Some(formatter) = pattern_formatter("console_pattern", &config.console_pattern)? {
+                console = console.with_formatter(formatter);
+            }
+            sink_configs.push(console);
+        }
+        if let Some(ref path) = config.log_filepath {
+            let path = expand_path(path, &config.application_name)?;
+            let mut file_sink = FileSink::new(&path)
+                .map_err(MlogError::io(format!("cannot open log file {}", path)))?
+                .with_rotation(config.rotation)
+                .with_compression(config.compression)
+                .with_retention(config.retention);
+            if config.file_per_session {
+                file_sink = file_sink.with_fresh_file_per_session();
+            }
+            if let Some(ref link) = config.current_link {
+                let link = expand_path(link, &config.application_name)
+                    .map_err(|e| ConfigError { key: "current_link".to_string(), ..e })?;
+                file_sink = file_sink.with_current_link(&link)
+                    .map_err(MlogError::io(format!("cannot create link {}", link)))?;
+            }
+            let file = match config.file_format {
+                OutputFormat::Text => {
+                    let file = SinkConfig::new(file_sink);
+                    match pattern_formatter("file_pattern", &config.file_pattern)? {
+                        Some(formatter) => file.with_formatter(formatter),
+                        None => file,
+                    }
+                }
+                OutputFormat::Json => {
+                    let formatter = JsonFormatter::new(&config.application_name);
+                    SinkConfig::new(file_sink.with_session_records(formatter.clone())).with_formatter(formatter)
+                }
+                OutputFormat::Logfmt => {
+                    let formatter = LogfmtFormatter::new(&config.application_name);
+                    SinkConfig::new(file_sink.with_session_records(formatter.clone())).with_formatter(formatter)
+                }
+            };
+            sink_configs.push(file.with_levels(config.file_levels));
+        }
+        sink_configs.append(&mut config.sinks);
 
-        let tmp_async_flag = config.async_flag.clone();
-        let tmp_mt_flag = config.multi_threaded_flag.clone();
-        let tmp_log_level = config.log_level.clone();
+        let tmp_async_flag = config.async_flag;
+        let tmp_mt_flag = config.multi_threaded_flag;
+        let tmp_filter = Logger::build_filter(&config)?;
+        let tmp_capacity = if tmp_async_flag { config.buffer_capacity } else { 0 };
+        let tmp_time_format = config.time_format.clone();
         
         
         // Initialize the logger with the configuration
         let logger = Arc::new(Logger {
             config,
-            log_level_mask: tmp_log_level as u8,
-            buffer: Default::default(),  // Initialize buffer
-            head: AtomicUsize::new(0),
-            tail: AtomicUsize::new(0),
-            should_run: Arc::new(AtomicBool::new(true)),
-            flush_interval: Duration::from_secs(5),  // Default flush interval
+            level_mask: AtomicU8::new(tmp_filter.default_mask()),
+            max_mask: AtomicU8::new(tmp_filter.max_mask()),
+            has_rules: AtomicBool::new(tmp_filter.has_rules()),
+            filter: RwLock::new(tmp_filter),
+            buffer: BoundedQueue::with_capacity(tmp_capacity),  // Initialize buffer
+            drain_lock: Mutex::new(()),
+            in_flight: AtomicUsize::new(0),
+            dropped_total: AtomicU64::new(0),
+            dropped_pending: Mutex::new((0, None)),
+            io_errors: AtomicU64::new(0),
+            signal: Arc::new(WriterSignal {
+                should_run: AtomicBool::new(true),
+                wake: Mutex::new(false),
+                writer_cv: Condvar::new(),
+                space_cv: Condvar::new(),
+                done: Mutex::new(false),
+                done_cv: Condvar::new(),
+            }),
+            writer_thread: Mutex::new(None),
             mutex: if tmp_mt_flag && !tmp_async_flag {
                 Some(Mutex::new(()))
             } else {
                 None
             },
-            file_writer: log_file,  // Only set up file writer if file path is provided
+            sinks: RwLock::new(Vec::new()),
+            next_sink_id: AtomicU64::new(0),
+            default_formatter: PatternFormatter::default().with_time_format(&tmp_time_format),
         });
 
-        // Log session start info if logging to a file
-        if let Some(ref writer) = logger.file_writer {
-            let mut writer_guard = writer.lock().unwrap();
-            
-            // Format the session start text
-            let session_text = format!(
-                "///////// {} : Session Started at {} /////////",
-                logger.config.application_name,
-                Local::now().format(&logger.config.time_format)
-            );
-        
-            // Calculate the length of the `-` line based on the session text length
-            let line_length = session_text.len();
-            let separator_line = "-".repeat(line_length);
-        
-            // Write the session start with dynamic separator lines
-            writeln!(
-                writer_guard,
-                "\n\n{}\n{}\n{}\n",
-                separator_line, session_text, separator_line
-            ).expect("Failed to write session start to log file");
-            
-            writer_guard.flush().expect("Failed to flush session start to log file");
+        // Attaching a sink starts its session (e.g. the file session banner)
+        for sink_config in sink_configs {
+            logger.try_add_sink(sink_config).map_err(MlogError::io("cannot start log session"))?;
         }
-        
 
-        // Spawn async flush thread if necessary
+        // Spawn async writer thread if necessary
         if logger.config.async_flag {
-            let logger_arc = Arc::clone(&logger);
-            let should_run = Arc::clone(&logger_arc.should_run);
-            thread::spawn(move || {
-                while should_run.load(Ordering::Relaxed) {
-                    thread::sleep(logger_arc.flush_interval);
-                    logger_arc.flush(); // Periodic flush
-                }
-            });
+            let logger_weak = Arc::downgrade(&logger);
+            let signal = Arc::clone(&logger.signal);
+            let max_latency = logger.config.max_latency;
+            let handle = thread::Builder::new()
+                .name("mlog-writer".to_string())
+                .spawn(move || Logger::run_writer(logger_weak, signal, max_latency))
+                .map_err(MlogError::io("cannot start async log writer"))?;
+            *logger.writer_thread.lock().unwrap() = Some(handle);
         }
-        logger
+        Ok(logger)
     }
 
-    pub fn write_log(&self, log_msg: &str) {
-        // Write to console if console_flag is enabled
-        if self.config.console_flag {
-            println!("{}", log_msg);
+    // MLOG_FILTER, when set, replaces `config.filter`. Bad directives in the config are an error,
+    // bad ones in the environment are reported and ignored.
+    fn build_filter(config: &LogConfig) -> Result<TargetFilter, ConfigError> {
+        if let Some((_, filter)) = Logger::env_filter(config.log_level) {
+            return Ok(filter);
         }
-    
-        // Write to the log file if file_writer is available
-        if let Some(ref file_writer) = self.file_writer {
-            let mut file_writer = file_writer.lock().unwrap();
-            writeln!(file_writer, "{}", log_msg).expect("Failed to write log to file");
-            file_writer.flush().expect("Failed to flush log file");
+        match config.filter {
+            Some(ref directives) => TargetFilter::parse(directives, config.log_level)
+                .map_err(|e| ConfigError { key: "filter".to_string(), value: directives.clone(), message: e.message }),
+            None => Ok(TargetFilter::new(config.log_level)),
         }
     }
 
+    // MLOG_FILTER and the filter it gives with `level` as the default, None when it is unset or invalid
+    fn env_filter(level: LogLevel) -> Option<(String, TargetFilter)> {
+        let directives = std::env::var(FILTER_ENV).ok()?;
+        match TargetFilter::parse(&directives, level) {
+            Ok(filter) => Some((directives, filter)),
+            Err(e) => {
+                eprintln!("mlog: ignoring {}: {}", FILTER_ENV, e);
+                None
+            }
+        }
+    }
 
-    pub fn log(&self, level: LogLevel, msg: &str, color: &str) {
+    // Async writer loop: sleeps until woken by a producer (batch ready, Crit record, full queue,
+    // shutdown) or until `max_latency` elapses, then drains the queue in batches
+    fn run_writer(logger: Weak<Logger>, signal: Arc<WriterSignal>, max_latency: Duration) {
+        loop {
+            {
+                let mut wake = signal.wake.lock().unwrap();
+                if !*wake && signal.should_run.load(Ordering::Acquire) {
+                    wake = signal.writer_cv.wait_timeout(wake, max_latency).unwrap().0;
+                }
+                *wake = false;
+            }
+
+            match logger.upgrade() {
+                Some(logger) => logger.flush(),
+                None => break,  // every Arc<Logger> is gone, Drop has drained the queue
+            }
 
-        if level as u8 > self.log_level_mask {
-            return;  // Skip this log, as the level is higher than the configured mask
+            if !signal.should_run.load(Ordering::Acquire) {
+                break;
+            }
         }
 
-        let time = Local::now();
-        let formatted_msg = format!(
-            "{}[{}] {} \x1b[0m",
-            color,
-            time.format(self.config.time_format.as_str()),
-            msg
-        );
+        *signal.done.lock().unwrap() = true;
+        signal.done_cv.notify_all();
+    }
 
-        if self.config.async_flag {
-            // Use atomics in async mode for lock-free writes
-            let head = self.head.load(Ordering::Relaxed);
-            let next_head = (head + 1) % BUFFER_CAPACITY;
-
-            // Check for buffer overflow
-            if next_head != self.tail.load(Ordering::Acquire) {
-                unsafe {
-                    (*self.buffer[head].get()) = Some(formatted_msg); // Write log to buffer
+    // Registers a sink on the running logger, returns the id to remove it with
+    pub fn add_sink(&self, sink_config: SinkConfig) -> SinkId {
+        self.try_add_sink(sink_config).expect("Failed to write session start to log sink")
+    }
+
+    // Like `add_sink`, returning the error if the sink cannot start its session
+    pub fn try_add_sink(&self, sink_config: SinkConfig) -> io::Result<SinkId> {
+        let id = SinkId(self.next_sink_id.fetch_add(1, Ordering::Relaxed));
+        let mut sink = sink_config.sink.into_inner().unwrap();
+
+        let time = Local::now().format(&self.config.time_format).to_string();
+        sink.session_start(&self.config.application_name, &time)?;
+
+        self.sinks.write().unwrap().push(SinkSlot {
+            id,
+            levels: sink_config.levels,
+            formatter: sink_config.formatter,
+            sink: Mutex::new(sink),
+            health: Mutex::new(SinkHealth::default()),
+        });
+        Ok(id)
+    }
+
+    // Detaches a sink (after writing out anything still queued for it) and hands it back
+    pub fn remove_sink(&self, id: SinkId) -> Option<Box<dyn Sink>> {
+        self.flush();
+
+        let slot = {
+            let mut sinks = self.sinks.write().unwrap();
+            let index = sinks.iter().position(|slot| slot.id == id)?;
+            sinks.remove(index)
+        };
+
+        let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        {
+            let mut sink = slot.sink.lock().unwrap_or_else(PoisonError::into_inner);
+            self.run_sink_op(&slot, SinkOperation::SessionEnd, None, || sink.session_end(&time));
+        }
+        Some(slot.sink.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn write_record(&self, record: &LogRecord) {
+        self.write_batch(std::slice::from_ref(record));
+    }
+
+    // Writes several records under one lock per sink and flushes each sink once
+    fn write_batch(&self, records: &[LogRecord]) {
+        let reentrant = reentrant();
+        let _writing = WritingGuard::enter();
+        let Some(sinks) = self.read_sinks(reentrant) else {
+            return;
+        };
+        for slot in sinks.iter() {
+            let formatter: &dyn Formatter = match slot.formatter {
+                Some(ref formatter) => formatter.as_ref(),
+                None => &self.default_formatter,
+            };
+
+            let Some(mut sink) = slot.lock(reentrant) else {
+                continue;
+            };
+            for record in records {
+                if slot.levels.contains(record.level) {
+                    let line = formatter.format(record);
+                    self.run_sink_op(slot, SinkOperation::Write, Some(&line), || sink.write(record, &line));
                 }
-                self.head.store(next_head, Ordering::Release);
-            } else {
-                eprintln!("Buffer overflow, dropping log message");
+            }
+            self.run_sink_op(slot, SinkOperation::Flush, None, || sink.flush());
+        }
+    }
+
+    fn read_sinks(&self, reentrant: bool) -> Option<RwLockReadGuard<'_, Vec<SinkSlot>>> {
+        if reentrant {
+            self.sinks.try_read().ok()
+        } else {
+            Some(self.sinks.read().unwrap())
+        }
+    }
+
+    // Runs a sink call, applying `config.io_error_policy` when it fails. `line` is the record
+    // being written, for writes.
+    fn run_sink_op(&self, slot: &SinkSlot, operation: SinkOperation, line: Option<&str>, mut op: impl FnMut() -> io::Result<()>) {
+        let error = match op() {
+            Ok(()) => {
+                if slot.succeeded(operation) && matches!(self.config.io_error_policy, IoErrorPolicy::Stderr) {
+                    eprintln!("mlog: log sink {} recovered", slot.id.0);
+                }
+                return;
+            }
+            Err(e) => e,
+        };
+        self.io_errors.fetch_add(1, Ordering::Relaxed);
+        let first_failure = slot.failed(operation);
+
+        match self.config.io_error_policy {
+            IoErrorPolicy::Ignore => {}
+            IoErrorPolicy::Stderr => {
+                if first_failure {
+                    eprintln!("mlog: {}, writing to stderr until it recovers", SinkError { sink: slot.id, operation, error: &error, line });
+                }
+                if let Some(line) = line {
+                    eprintln!("{}", strip_ansi(line));
+                }
+            }
+            IoErrorPolicy::Retry { attempts, mut backoff } => {
+                // All attempts on the first failure only. While the sink stays down a single retry
+                // is made per RETRY_RECHECK_INTERVAL, and other failed records are dropped right away,
+                // so logging threads do not all sleep on a sink that cannot recover yet.
+                let attempts = {
+                    let health = slot.health.lock().unwrap();
+                    match health.retry_at {
+                        Some(retry_at) if Instant::now() < retry_at => return,
+                        _ if first_failure => attempts,
+                        _ => attempts.min(1),
+                    }
+                };
+                for _ in 0..attempts {
+                    thread::sleep(backoff);
+                    if op().is_ok() {
+                        *slot.health.lock().unwrap() = SinkHealth::default();
+                        return;
+                    }
+                    self.io_errors.fetch_add(1, Ordering::Relaxed);
+                    backoff = backoff.saturating_mul(2);
+                }
+                // Given up, the record is dropped
+                slot.health.lock().unwrap().retry_at = Some(Instant::now() + RETRY_RECHECK_INTERVAL);
+            }
+            IoErrorPolicy::Callback(ref callback) => callback(&SinkError { sink: slot.id, operation, error: &error, line }),
+        }
+    }
+
+    // Number of failed sink writes and flushes (including failed retries) since the logger started
+    pub fn io_error_count(&self) -> u64 {
+        self.io_errors.load(Ordering::Relaxed)
+    }
+
+
+    pub fn log(&self, level: LogLevel, msg: &str, color: &'static str) {
+        self.log_with_target(level, "", msg, color);
+    }
+
+    // Same as `log`, tagging the record with a target (the logging macros pass `module_path!()`)
+    pub fn log_with_target(&self, level: LogLevel, target: &str, msg: &str, color: &'static str) {
+        if self.enabled_for(level, target) {
+            self.log_unfiltered(LogRecord::new(level, target, msg.to_string(), color));
+        }
+    }
+
+    // Whether `level` is enabled for at least one target
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        self.max_mask.load(Ordering::Relaxed) & level.bit() != 0
+    }
+
+    pub fn enabled_for(&self, level: LogLevel, target: &str) -> bool {
+        if !self.enabled(level) {
+            return false;
+        }
+        let mask = if self.has_rules.load(Ordering::Relaxed) {
+            self.filter.read().unwrap().rule_mask(target)
+        } else {
+            None
+        };
+        mask.unwrap_or_else(|| self.level_mask.load(Ordering::Relaxed)) & level.bit() != 0
+    }
+
+    // Level for targets without a per-target rule. None if the filter turned them off, or if they
+    // have a set of levels that is not a threshold, such as Crit and Success: see `levels` for those.
+    pub fn level(&self) -> Option<LogLevel> {
+        self.filter.read().unwrap().default_level()
+    }
+
+    // Changes the level of a running logger; per-target rules stay as they are. Takes effect for
+    // every thread's next record (records already queued in async mode are still written).
+    pub fn set_level(&self, level: LogLevel) {
+        let mut filter = self.filter.write().unwrap();
+        filter.set_default_level(level);
+        self.publish_filter(&filter);
+    }
+
+    // Exact levels for targets without a per-target rule
+    pub fn levels(&self) -> LevelSet {
+        self.filter.read().unwrap().default_levels()
+    }
+
+    // Like `set_level`, with any set of levels, e.g. `LevelSet::only(&[LogLevel::Crit, LogLevel::Success])`
+    pub fn set_levels(&self, levels: LevelSet) {
+        let mut filter = self.filter.write().unwrap();
+        filter.set_default_levels(levels);
+        self.publish_filter(&filter);
+    }
+
+    pub fn filter(&self) -> TargetFilter {
+        self.filter.read().unwrap().clone()
+    }
+
+    // Replaces the level and per-target directives of a running logger
+    pub fn set_filter(&self, filter: TargetFilter) {
+        let mut current = self.filter.write().unwrap();
+        self.publish_filter(&filter);
+        *current = filter;
+    }
+
+    // Mirrors the filter into the atomics read on the hot path (called with the filter lock held)
+    fn publish_filter(&self, filter: &TargetFilter) {
+        self.level_mask.store(filter.default_mask(), Ordering::Relaxed);
+        self.max_mask.store(filter.max_mask(), Ordering::Relaxed);
+        self.has_rules.store(filter.has_rules(), Ordering::Relaxed);
+        #[cfg(feature = "log")]
+        if global_logger().is_some_and(|global| std::ptr::eq(self, &*global)) {
+            log_bridge::set_max_level(filter.max_level());
+        }
+    }
+
+    // Sends an already built record to the outputs (queued in async mode)
+    pub fn log_record(&self, record: LogRecord) {
+        if !self.enabled_for(record.level, &record.target) {
+            return;  // Skip this log, as the level is higher than the mask for its target
+        }
+        self.log_unfiltered(record);
+    }
+
+    // For the macros, which have already checked `enabled_for`; saves a second filter lookup
+    #[doc(hidden)]
+    pub fn __log_checked(&self, record: LogRecord) {
+        self.log_unfiltered(record);
+    }
+
+    // The logger's own notices (e.g. config reloads), which should not depend on the filter,
+    // and records whose level and target were checked already
+    pub(crate) fn log_unfiltered(&self, record: LogRecord) {
+        let level = record.level;
+
+        if reentrant() {
+            // From inside a sink write on this thread: the queue or `mutex` would wait on ourselves
+            self.write_record(&record);
+
+        } else if self.config.async_flag {
+            self.enqueue(record);
+
+            // Crit records go out right away, everything else once a batch has built up
+            if level == LogLevel::Crit || self.buffer.len() >= self.config.max_batch_size {
+                self.signal.wake_writer();
             }
 
         } else if self.config.multi_threaded_flag {
             // Use mutex for thread-safe access when async is disabled but multi-threaded is enabled
-            let _lock = self.mutex.as_ref().unwrap().lock().unwrap();
-            self.write_log(&formatted_msg);  // Write to file and/or console
+            let _lock = self.mutex.as_ref().unwrap().lock().unwrap_or_else(PoisonError::into_inner);
+            self.write_record(&record);  // Write to every sink
 
         } else {
             // Single-threaded, non-async mode: log immediately
-            self.write_log(&formatted_msg);  // Write to file and/or console
-        }
-    }
-
-
-    pub fn rotate_logs(&self, writer: &mut BufWriter<File>) {
-        if let Some(ref path) = self.config.log_filepath {
-            if let Ok(metadata) = fs::metadata(path) {
-                if metadata.len() > MAX_LOG_FILE_SIZE {
-                    // Ensure no race condition by locking and checking the file size again
-                    writer.flush().expect("Failed to flush before rotation");
-    
-                    // Prepare the rotated file path with a more precise timestamp to avoid conflicts
-                    let rotated_path = format!("{}.{}", path, Local::now().format("%Y%m%d%H%M%S%.3f"));
-    
-                    // Rename the current log file
-                    fs::rename(path, &rotated_path).expect("Failed to rotate log file");
-    
-                    // Re-open the log file and replace the BufWriter within the Mutex
-                    let new_file = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(path)
-                        .expect("Failed to open new log file after rotation");
-                    *writer = BufWriter::new(new_file);
-    
-                    // Write log rotation message
-                    writeln!(
-                        writer,
-                        "\n\n--- Log rotated at {} ---",
-                        Local::now().format("%Y-%m-%d %H:%M:%S%.3f")
-                    ).expect("Failed to write log rotation message");
-                    writer.flush().expect("Failed to flush after log rotation");
+            self.write_record(&record);  // Write to every sink
+        }
+    }
+
+
+    // Lock-free push into the async queue, applying the overflow policy when it is full
+    fn enqueue(&self, mut record: LogRecord) {
+        let policy = self.config.overflow_policy;
+        let level = record.level;
+
+        if policy == OverflowPolicy::ShedByLevel && level != LogLevel::Crit {
+            // Each level may only fill part of the queue: Info gets half of it, every level above
+            // gets another eighth (Debug and Trace an eighth less each), so the remaining room is
+            // kept for more severe records
+            let level_count = (level as u8).count_ones() as usize;
+            let limit = self.buffer.capacity() * (9 - level_count) / 8;
+            if self.buffer.len() >= limit {
+                self.record_drop();
+                return;
+            }
+        }
+
+        loop {
+            record = match self.buffer.push(record) {
+                Ok(()) => return,
+                Err(record) => record,
+            };
+
+            match policy {
+                OverflowPolicy::DropNewest => {
+                    self.record_drop();
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    if self.buffer.pop().is_some() {
+                        self.record_drop();
+                    }
+                }
+                OverflowPolicy::Block | OverflowPolicy::ShedByLevel => {
+                    if self.signal.should_run.load(Ordering::Acquire) {
+                        // Kick the writer and wait for it to free some slots; the timeout covers
+                        // a wake-up that lands between our push attempt and the wait
+                        let mut wake = self.signal.wake.lock().unwrap();
+                        *wake = true;
+                        self.signal.writer_cv.notify_one();
+                        let _ = self.signal.space_cv.wait_timeout(wake, Duration::from_millis(10)).unwrap();
+                    } else {
+                        // Writer thread is gone, make room ourselves
+                        self.flush();
+                    }
                 }
             }
         }
     }
 
-    pub fn flush(&self) {
-        if self.config.async_flag {
-            // In async mode, ensure atomic and thread-safe flush
-            let mut tail = self.tail.load(Ordering::Acquire); // Get the current tail position
-            let head = self.head.load(Ordering::Acquire);     // Get the current head position
-    
-            while tail != head {
-                // Load the message at the tail position atomically
-                let log_msg_opt = unsafe { (*self.buffer[tail].get()).take() };
-                
-                if let Some(log_msg) = log_msg_opt {
-                    self.write_log(&log_msg); // Write log to file and console
+    fn record_drop(&self) {
+        self.dropped_total.fetch_add(1, Ordering::Relaxed);
+        let mut pending = self.dropped_pending.lock().unwrap();
+        if pending.0 == 0 {
+            pending.1 = Some(Local::now());
+        }
+        pending.0 += 1;
+    }
+
+    // Number of records dropped by the overflow policy since the logger started
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped_total.load(Ordering::Relaxed)
+    }
+
+    // Writes out everything queued in batches, then a summary of any drops. Caller must hold `drain_lock`.
+    fn drain_queue(&self) {
+        let batch_size = self.config.max_batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+        loop {
+            // capacity() may exceed the request, so the limit is counted explicitly
+            while batch.len() < batch_size {
+                match self.buffer.pop() {
+                    Some(record) => batch.push(record),
+                    None => break,
                 }
-    
-                // Move the tail forward in a circular manner
-                tail = (tail + 1) % BUFFER_CAPACITY;
-    
-                // Atomically store the updated tail position
-                self.tail.store(tail, Ordering::Release);
             }
-        } else if !self.config.multi_threaded_flag {
-            // in multi-threaded mode, mutex ensures thread-safe logging, so no flush is required --
-            // if neither async nor multi-threaded, just flush directly
-            if let Some(ref writer) = self.file_writer {
-                let mut writer_guard = writer.lock().unwrap();
-                self.rotate_logs(&mut writer_guard);  // Check for rotation before flushing
-                writer_guard.flush().expect("Failed to flush log file");
+            if batch.is_empty() {
+                break;
+            }
+
+            self.in_flight.store(batch.len(), Ordering::Release);
+            self.write_batch(&batch); // Write batch to every sink
+            self.in_flight.store(0, Ordering::Release);
+            batch.clear();
+
+            // Slots were freed, let blocked producers retry
+            self.signal.space_cv.notify_all();
+        }
+
+        let (count, since) = std::mem::take(&mut *self.dropped_pending.lock().unwrap());
+        if count > 0 {
+            let since = since.map(|t| t.format("%H:%M:%S").to_string()).unwrap_or_default();
+            let summary = format!("{} messages dropped since {}", count, since);
+            self.write_record(&LogRecord::new(LogLevel::Warn, "mlog", summary, CONSOLE_COLOR_WARN));
+        }
+    }
+
+    pub fn flush(&self) {
+        // A thread already writing (e.g. the writer shutting down from the panic hook) may hold
+        // `drain_lock` further up its stack, so it leaves the queue alone
+        let reentrant = reentrant();
+        if self.config.async_flag && !reentrant {
+            // Only one thread drains at a time so records keep their queue order, and a flush()
+            // from the caller waits for a drain already running on the background thread
+            let _drain = self.drain_lock.lock().unwrap_or_else(PoisonError::into_inner);
+            let _writing = WritingGuard::enter();
+            self.drain_queue();
+        }
+
+        let Some(sinks) = self.read_sinks(reentrant) else {
+            return;
+        };
+        for slot in sinks.iter() {
+            if let Some(mut sink) = slot.lock(reentrant) {
+                self.run_sink_op(slot, SinkOperation::Flush, None, || sink.flush());
             }
         }
     }
 
-    pub fn shutdown(&self) {
+    // Stops the async writer, waiting up to `shutdown_timeout` for it to drain the queue and exit.
+    // Calling it again after a successful shutdown is a no-op apart from the session end marker.
+    pub fn shutdown(&self) -> Result<(), ShutdownTimeout> {
         if self.config.async_flag {
-            self.should_run.store(false, Ordering::Relaxed);  // signal async thread to stop
+            self.signal.should_run.store(false, Ordering::Release);  // signal async thread to stop
+            self.signal.wake_writer();
+
+            let handle = self.writer_thread.lock().unwrap().take();
+            if let Some(handle) = handle {
+                if handle.thread().id() == thread::current().id() {
+                    // Shutdown from the writer itself (e.g. the panic hook after a sink panicked):
+                    // nobody to wait for, and the drain it is in the middle of is left as it is
+                    self.flush();
+                } else if self.signal.wait_done(self.config.shutdown_timeout) {
+                    let _ = handle.join();
+                } else {
+                    // Leave the writer detached, it keeps draining in the background
+                    let pending = self.buffer.len() + self.in_flight.load(Ordering::Acquire);
+                    return Err(ShutdownTimeout { pending });
+                }
+            }
         }
         
         self.flush();  // Ensure remaining logs are flushed before shutting down
         
-        // write session start info if logging to file
-        if let Some(ref writer) = self.file_writer {
-            let mut writer_guard = writer.lock().unwrap();
-            writeln!(
-                writer_guard,
-                "\n------ Session Ended at {} ------ \n",
-                Local::now().format("%Y-%m-%d %H:%M:%S")
-            ).expect("Failed to write session start to log file");
-            writer_guard.flush().expect("Failed to flush session start to log file");
+        // write session end info to every sink (the session banner for log files)
+        let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let reentrant = reentrant();
+        let Some(sinks) = self.read_sinks(reentrant) else {
+            return Ok(());
+        };
+        for slot in sinks.iter() {
+            if let Some(mut sink) = slot.lock(reentrant) {
+                self.run_sink_op(slot, SinkOperation::SessionEnd, None, || sink.session_end(&time));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        // Dropped without shutdown: stop the writer and write out whatever is still queued
+        if self.config.async_flag && self.signal.should_run.swap(false, Ordering::AcqRel) {
+            self.signal.wake_writer();
+            self.flush();
         }
     }
 }
 
-// Global static pointer for logger
-static LOGGER: AtomicPtr<Logger> = AtomicPtr::new(ptr::null_mut());
+// Global logger. Users clone the Arc and drop the lock before logging, so `shutdown` can take it
+// out at any time (even from inside a sink or the panic hook) without freeing it under a writer.
+static LOGGER: RwLock<Option<Arc<Logger>>> = RwLock::new(None);
 
 /* log_level, application_name, Opt<filepath>, Opt<console_flag>, Opt<async_flag>, Opt<multithreaded_flag> */
 pub fn init(config: LogConfig) {
-    let logger = Logger::new(config);
+    try_init(config).unwrap_or_else(|e| panic!("Failed to initialize logger: {}", e));
+}
 
-    let logger_ptr = Arc::into_raw(logger) as *mut Logger;
+// Installs the global logger, or returns why it could not: an I/O error (e.g. the log file cannot be
+// opened), an invalid setting, or a logger that is already installed (which is left as it is)
+pub fn try_init(config: LogConfig) -> Result<(), MlogError> {
+    if global_logger().is_some() {
+        return Err(MlogError::AlreadyInitialized);  // checked first so no file is touched
+    }
+    let logger = Logger::try_new(config)?;
+    #[cfg(feature = "log")]
+    let max_level = logger.filter.read().unwrap().max_level();
 
-    LOGGER
-        .compare_exchange(ptr::null_mut(), logger_ptr, Ordering::SeqCst, Ordering::SeqCst)
-        .expect("Logger is already initialized!");
+    {
+        let mut global = LOGGER.write().unwrap_or_else(PoisonError::into_inner);
+        if global.is_some() {
+            // Another thread won the race; close this logger again
+            drop(global);
+            let _ = logger.shutdown();
+            return Err(MlogError::AlreadyInitialized);
+        }
+        *global = Some(logger);
+    }
+
+    #[cfg(feature = "log")]
+    log_bridge::install(max_level);
 
     init_panic_hook();
+    Ok(())
 }
 
 
-pub fn shutdown() {
-    let logger_ptr = LOGGER.swap(ptr::null_mut(), Ordering::SeqCst);
-    if !logger_ptr.is_null() {
-        unsafe {
-            let logger: Arc<Logger> = Arc::from_raw(logger_ptr); // Convert back to Arc<Logger>
-            logger.shutdown();  // Flush and shutdown
-        }
+pub fn shutdown() -> Result<(), ShutdownTimeout> {
+    // Taken out first, so the lock is not held while flushing; callers that still hold a
+    // reference keep the logger alive until they are done with it
+    let logger = LOGGER.write().unwrap_or_else(PoisonError::into_inner).take();
+    match logger {
+        Some(logger) => logger.shutdown(),  // Flush and shutdown
+        None => Ok(()),
     }
 }
 
+// Changes the level of the global logger, see `Logger::set_level`
+pub fn set_level(level: LogLevel) {
+    with_logger(|logger| logger.set_level(level));
+}
+
+// Level of the global logger, None if it is not initialized (or its default is off or not a threshold, see `Logger::level`)
+pub fn level() -> Option<LogLevel> {
+    with_logger_opt(|logger| logger.level()).flatten()
+}
+
+// The logger installed by `mlog::init`, as a new reference
+pub(crate) fn global_logger() -> Option<Arc<Logger>> {
+    LOGGER.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+// Like `with_logger`, but returns None instead of panicking when no logger is installed
+pub fn with_logger_opt<R, F: FnOnce(&Logger) -> R>(f: F) -> Option<R> {
+    global_logger().map(|logger| f(&logger))
+}
+
 pub fn with_logger<F: FnOnce(&Logger)>(f: F) {
-    let logger_ptr = LOGGER.load(Ordering::SeqCst);
-    if !logger_ptr.is_null() {
-        unsafe { f(&*logger_ptr); }
-    } else {
-        panic!("Logger is not initialized!");
+    match global_logger() {
+        Some(logger) => f(&logger),
+        None => panic!("Logger is not initialized!"),
     }
 }
 
 
+// Shared body of the level macros. Either a plain `format!` argument list, or structured fields
+// followed by `;` and the message: `info!(user_id = 42, latency_ms = 3.1; "request done")`.
+// A field is `key = value`, `key = %value` (Display), `key = ?value` (Debug), or the shorthands
+// `key`, `%key` and `?key` for a variable of the same name.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log {
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] ; $($arg:tt)+) => {
+        $crate::with_logger(|logger| {
+            if logger.enabled_for($level, module_path!()) {
+                logger.__log_checked(
+                    $crate::LogRecord::new($level, module_path!(), format!($($arg)+), $color)
+                        .with_callsite(module_path!(), file!(), line!())
+                        .with_fields(vec![$($fields),*]),
+                );
+            }
+        })
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = % $value:expr , $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::Str(format!("{}", $value))),] $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = % $value:expr ; $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::Str(format!("{}", $value))),] ; $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = ? $value:expr , $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::Str(format!("{:?}", $value))),] $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = ? $value:expr ; $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::Str(format!("{:?}", $value))),] ; $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = $value:expr , $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::from($value)),] $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident = $value:expr ; $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)* $crate::__field!($key, $crate::Value::from($value)),] ; $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] % $key:ident $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)*] $key = % $key $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] ? $key:ident $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)*] $key = ? $key $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident , $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)*] $key = $key , $($rest)+)
+    };
+    (@fields $level:expr, $color:expr, [$($fields:expr,)*] $key:ident ; $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [$($fields,)*] $key = $key ; $($rest)+)
+    };
+
+    ($level:expr, $color:expr, $fmt:literal $($arg:tt)*) => {
+        $crate::__log!(@fields $level, $color, [] ; $fmt $($arg)*)
+    };
+    ($level:expr, $color:expr, $($rest:tt)+) => {
+        $crate::__log!(@fields $level, $color, [] $($rest)+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __field {
+    ($key:ident, $value:expr) => {
+        (stringify!($key).to_string(), $value)
+    };
+}
+
+#[cfg(not(feature = "performance"))]
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::__log!($crate::LogLevel::Trace, $crate::CONSOLE_COLOR_TRACE, $($arg)*)
+    };
+}
+
+#[cfg(feature = "performance")]
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(not(feature = "performance"))]
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::__log!($crate::LogLevel::Debug, $crate::CONSOLE_COLOR_DEBUG, $($arg)*)
+    };
+}
+
+#[cfg(feature = "performance")]
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {};
+}
+
 #[cfg(not(feature = "performance"))]
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {
-        with_logger(|logger| logger.log(LogLevel::Info, &format!($($arg)*), CONSOLE_COLOR_INFO));
+        $crate::__log!($crate::LogLevel::Info, $crate::CONSOLE_COLOR_INFO, $($arg)*)
     };
 }
 
@@ -351,7 +1215,7 @@ macro_rules! info {
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {
-        with_logger(|logger| logger.log(LogLevel::Warn, &format!($($arg)*), CONSOLE_COLOR_WARN));
+        $crate::__log!($crate::LogLevel::Warn, $crate::CONSOLE_COLOR_WARN, $($arg)*)
     };
 }
 
@@ -365,7 +1229,7 @@ macro_rules! warn {
 #[macro_export]
 macro_rules! success {
     ($($arg:tt)*) => {
-        with_logger(|logger| logger.log(LogLevel::Success, &format!($($arg)*), CONSOLE_BG_COLOR_SUCCESS));
+        $crate::__log!($crate::LogLevel::Success, $crate::CONSOLE_BG_COLOR_SUCCESS, $($arg)*)
     };
 }
 
@@ -378,21 +1242,21 @@ macro_rules! success {
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)*) => {
-        with_logger(|logger| logger.log(LogLevel::Error, &format!($($arg)*), CONSOLE_COLOR_PINK));
+        $crate::__log!($crate::LogLevel::Error, $crate::CONSOLE_COLOR_PINK, $($arg)*)
     };
 }
 
 #[macro_export]
 macro_rules! crit {
     ($($arg:tt)*) => {
-        with_logger(|logger| logger.log(LogLevel::Crit, &format!($($arg)*), CONSOLE_BG_COLOR_RED));
+        $crate::__log!($crate::LogLevel::Crit, $crate::CONSOLE_BG_COLOR_RED, $($arg)*)
     };
 }
 
 #[macro_export]
 macro_rules! log_flush {
     () => {
-        with_logger(|logger| logger.flush());
+        $crate::with_logger(|logger| logger.flush());
     };
 }
 
@@ -400,13 +1264,13 @@ macro_rules! log_flush {
 macro_rules! log_assert {
     ($cond:expr) => {
         if !$cond {
-            crit!("Assertion failed: {}", stringify!($cond));
+            $crate::crit!("Assertion failed: {}", stringify!($cond));
             panic!("Assertion failed: {}", stringify!($cond));
         }
     };
     ($cond:expr, $($arg:tt)*) => {
         if !$cond {
-            crit!("Assertion failed: {}", format!($($arg)*));
+            $crate::crit!("Assertion failed: {}", format!($($arg)*));
             panic!("Assertion failed: {}", format!($($arg)*));
         }
     };
@@ -414,21 +1278,35 @@ macro_rules! log_assert {
 
 
 pub fn init_panic_hook() {
-    panic::set_hook(Box::new(|info| {
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
         // Extract panic location and message
         let location = info.location()
             .map(|loc| format!("file '{}' at line {}", loc.file(), loc.line()))
             .unwrap_or_else(|| "unknown location".to_string());
 
         let payload = info.payload().downcast_ref::<&str>()
-            .map(|msg| *msg)
+            .copied()
             .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
             .unwrap_or("Unknown panic message");
 
-        // Log the panic information with high priority (e.g., Critical level)
-        crit!("Panic occurred! Message: '{}' at {}", payload, location);
+        // Log the panic information with high priority (e.g., Critical level), unless the
+        // logger has already been shut down (with_logger would panic inside the hook)
+        if let Some(logger) = global_logger() {
+            // The record points at the panic site rather than at this hook
+            let message = format!("Panic occurred! Message: '{}' at {}", payload, location);
+            let mut record = LogRecord::new(LogLevel::Crit, "panic", message, CONSOLE_BG_COLOR_RED);
+            if let Some(loc) = info.location() {
+                record.file = Some(loc.file().to_string());
+                record.line = Some(loc.line());
+            }
+            logger.log_record(record);
+            drop(logger);
+
+            // Flush the logger to ensure all logs are written before the program exits
+            let _ = shutdown();
+        }
 
-        // Flush the logger to ensure all logs are written before the program exits
-        shutdown();
+        previous_hook(info);
     }));
 }
@@ -0,0 +1,91 @@
+// `log` crate facade (cargo feature "log"): records from dependencies that use `log::info!` etc.
+// are turned into mlog records and go through the same outputs as mlog's own macros.
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{with_logger_opt, LogLevel, LogRecord, Logger};
+
+// log has no Success or Crit
+fn to_log_level(level: Level) -> LogLevel {
+    match level {
+        Level::Error => LogLevel::Error,
+        Level::Warn => LogLevel::Warn,
+        Level::Info => LogLevel::Info,
+        Level::Debug => LogLevel::Debug,
+        Level::Trace => LogLevel::Trace,
+    }
+}
+
+fn to_level_filter(level: Option<LogLevel>) -> LevelFilter {
+    match level {
+        Some(LogLevel::Trace) => LevelFilter::Trace,
+        Some(LogLevel::Debug) => LevelFilter::Debug,
+        Some(LogLevel::Info) => LevelFilter::Info,
+        Some(LogLevel::Success) | Some(LogLevel::Warn) => LevelFilter::Warn,
+        Some(LogLevel::Error) | Some(LogLevel::Crit) => LevelFilter::Error,
+        None => LevelFilter::Off,
+    }
+}
+
+fn to_record(record: &Record) -> LogRecord {
+    let level = to_log_level(record.level());
+    let mut log_record = LogRecord::new(level, record.target(), record.args().to_string(), level.color());
+    log_record.module_path = record.module_path().map(str::to_string);
+    log_record.file = record.file().map(str::to_string);
+    log_record.line = record.line();
+    log_record
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.enabled_for(to_log_level(metadata.level()), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if Log::enabled(self, record.metadata()) {
+            self.log_unfiltered(to_record(record));
+        }
+    }
+
+    fn flush(&self) {
+        Logger::flush(self);
+    }
+}
+
+// Installed with `log::set_logger`, which only accepts a `&'static dyn Log` once per process, so
+// it forwards to whichever logger `mlog::init` installed last (and drops records while there is none)
+struct GlobalLogger;
+
+static GLOBAL_LOGGER: GlobalLogger = GlobalLogger;
+static INSTALLED: AtomicBool = AtomicBool::new(false);  // `log::set_logger` took GLOBAL_LOGGER
+
+impl Log for GlobalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        with_logger_opt(|logger| Log::enabled(logger, metadata)).unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        with_logger_opt(|logger| Log::log(logger, record));
+    }
+
+    fn flush(&self) {
+        with_logger_opt(Log::flush);
+    }
+}
+
+// `max_level` is the most verbose level any target can log at
+pub(crate) fn install(max_level: Option<LogLevel>) {
+    // Fails if another `log` implementation is already set (or ours from a previous init), both fine
+    if log::set_logger(&GLOBAL_LOGGER).is_ok() {
+        INSTALLED.store(true, Ordering::SeqCst);
+    }
+    set_max_level(max_level);
+}
+
+// Called when the global logger's filter changes. The max level belongs to whichever logger `log`
+// forwards to, so another implementation's is left alone.
+pub(crate) fn set_max_level(max_level: Option<LogLevel>) {
+    if INSTALLED.load(Ordering::SeqCst) {
+        log::set_max_level(to_level_filter(max_level));
+    }
+}
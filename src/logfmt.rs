@@ -0,0 +1,97 @@
+use std::fmt::Write;
+use chrono::SecondsFormat;
+
+use crate::color::strip_ansi;
+use crate::sink::Formatter;
+use crate::LogRecord;
+
+// logfmt formatter: `ts=... level=... app=... msg="..."` followed by thread, target, the source
+// location when known, and the record's structured fields as further `key=value` pairs
+#[derive(Clone, Debug)]
+pub struct LogfmtFormatter {
+    application_name: String,
+}
+
+impl LogfmtFormatter {
+    pub fn new(application_name: &str) -> Self {
+        LogfmtFormatter { application_name: application_name.to_string() }
+    }
+}
+
+impl Formatter for LogfmtFormatter {
+    fn format(&self, record: &LogRecord) -> String {
+        let mut out = String::with_capacity(128 + record.message.len());
+
+        out.push_str("ts=");
+        out.push_str(&record.time.to_rfc3339_opts(SecondsFormat::Millis, false));
+        out.push_str(" level=");
+        out.push_str(record.level.as_str());
+        out.push_str(" app=");
+        write_logfmt_value(&mut out, &self.application_name);
+        out.push_str(" msg=");
+        write_quoted(&mut out, &strip_ansi(&record.message));  // Always quoted, so msg is easy to spot
+        out.push_str(" thread=");
+        write_logfmt_value(&mut out, &record.thread);
+        out.push_str(" target=");
+        write_logfmt_value(&mut out, &record.target);
+        if let Some(ref module_path) = record.module_path {
+            out.push_str(" module_path=");
+            write_logfmt_value(&mut out, module_path);
+        }
+        if let Some(ref file) = record.file {
+            out.push_str(" file=");
+            write_logfmt_value(&mut out, file);
+        }
+        if let Some(line) = record.line {
+            let _ = write!(out, " line={}", line);
+        }
+
+        for (key, value) in &record.fields {
+            out.push(' ');
+            write_logfmt_key(&mut out, key);
+            out.push('=');
+            write_logfmt_value(&mut out, &value.to_string());
+        }
+        out
+    }
+}
+
+// Keys cannot be quoted in logfmt, so characters that would break the pair become '_'
+pub(crate) fn write_logfmt_key(out: &mut String, key: &str) {
+    if key.is_empty() {
+        out.push('_');
+    }
+    out.extend(key.chars().map(|c| if needs_quotes(c) { '_' } else { c }));
+}
+
+// Bare value when possible; quoted (with escapes) when empty or when it contains spaces, quotes,
+// '=' or control characters
+pub(crate) fn write_logfmt_value(out: &mut String, value: &str) {
+    if value.is_empty() || value.chars().any(needs_quotes) {
+        write_quoted(out, value);
+    } else {
+        out.push_str(value);
+    }
+}
+
+fn needs_quotes(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || c == '"' || c == '=' || c == '\\'
+}
+
+fn write_quoted(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
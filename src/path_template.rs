@@ -0,0 +1,91 @@
+use std::env;
+use chrono::Local;
+use chrono::format::{Item, StrftimeItems};
+
+use crate::ConfigError;
+
+// Expands a log file path template, e.g. `~/logs/{app}/{date}-{pid}.log`:
+//
+//   {app}           application name, with path separators replaced by `_`
+//   {date}          start date, `%Y-%m-%d`, or `{date:<strftime>}` for another format
+//   {time}          start time, `%H%M%S`
+//   {pid}           process id
+//   ~               at the start, the home directory
+//   $VAR, ${VAR}    environment variables; a `$` not followed by a letter, `_` or `{` is kept as it is
+//
+// `{{` and `}}` are literal braces. Unknown placeholders and unset variables are errors.
+pub fn expand_path(template: &str, application_name: &str) -> Result<String, ConfigError> {
+    let now = Local::now();
+    let error = |message: String| ConfigError { key: "log_filepath".to_string(), value: template.to_string(), message };
+
+    let mut out = String::new();
+    let mut rest = template;
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            out.push_str(&home_dir().ok_or_else(|| error("cannot expand ~, HOME is not set".to_string()))?);
+            rest = after;
+        }
+    }
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '{' if rest.starts_with('{') => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+            '}' if rest.starts_with('}') => {
+                out.push('}');
+                rest = &rest[1..];
+            }
+            '{' => {
+                let end = rest.find('}').ok_or_else(|| error("unclosed '{'".to_string()))?;
+                let (name, spec) = match rest[..end].split_once(':') {
+                    Some((name, spec)) => (name, Some(spec)),
+                    None => (&rest[..end], None),
+                };
+                match (name, spec) {
+                    ("app", None) => out.push_str(&application_name.replace(['/', '\\'], "_")),
+                    ("date", spec) => {
+                        let format = spec.unwrap_or("%Y-%m-%d");
+                        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                            return Err(error(format!("invalid date format {:?}", format)));
+                        }
+                        out.push_str(&now.format(format).to_string());
+                    }
+                    ("time", None) => out.push_str(&now.format("%H%M%S").to_string()),
+                    ("pid", None) => out.push_str(&std::process::id().to_string()),
+                    _ => return Err(error(format!("unknown placeholder {{{}}}", &rest[..end]))),
+                }
+                rest = &rest[end + 1..];
+            }
+            '}' => return Err(error("unmatched '}'".to_string())),
+            '$' => {
+                let (name, after) = match rest.strip_prefix('{') {
+                    Some(braced) => {
+                        let end = braced.find('}').ok_or_else(|| error("unclosed '${'".to_string()))?;
+                        (&braced[..end], &braced[end + 1..])
+                    }
+                    None if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                        (&rest[..end], &rest[end..])
+                    }
+                    None => ("", rest),
+                };
+                if name.is_empty() {
+                    out.push('$');  // a lone `$` is kept as it is
+                    continue;
+                }
+                let value = env::var(name).map_err(|_| error(format!("environment variable {} is not set", name)))?;
+                out.push_str(&value);
+                rest = after;
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn home_dir() -> Option<String> {
+    env::var("HOME").ok().or_else(|| env::var("USERPROFILE").ok()).filter(|home| !home.is_empty())
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{self, SystemTime};
+use chrono::{DateTime, Duration, Local, NaiveTime, Timelike};
+
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;  // 10 MB, the size rotation has always used
+const STALE_PARTIAL_AGE: time::Duration = time::Duration::from_secs(60);  // a `.tmp` untouched this long was left by a crash
+
+
+// When the log file is rotated (renamed to `path.<timestamp>` and started afresh). Size and time
+// limits can be combined; whichever is reached first rotates the file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub max_size: Option<u64>,                // Rotate before a write would take the file past this many bytes
+    pub interval: Option<RotationInterval>,  // Rotate on a schedule
+}
+
+// Schedules for time-based rotation, in local time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RotationInterval {
+    Hourly,    // At the start of every hour
+    Daily,     // 24 hours after the file was opened
+    Midnight,  // At local midnight
+}
+
+impl RotationPolicy {
+    pub const fn never() -> Self {
+        RotationPolicy { max_size: None, interval: None }
+    }
+
+    pub const fn size(max_size: u64) -> Self {
+        RotationPolicy { max_size: Some(max_size), interval: None }
+    }
+
+    pub const fn time(interval: RotationInterval) -> Self {
+        RotationPolicy { max_size: None, interval: Some(interval) }
+    }
+
+    pub const fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub const fn with_interval(mut self, interval: RotationInterval) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    // Whether `incoming` more bytes on top of `size` call for a new file. A single line larger than
+    // `max_size` still goes into an empty file rather than rotating forever.
+    pub(crate) fn size_exceeded(&self, size: u64, incoming: u64) -> bool {
+        match self.max_size {
+            Some(max_size) => size > 0 && size + incoming > max_size,
+            None => false,
+        }
+    }
+
+    // When a file opened (or rotated) at `opened` is due for rotation, None without an interval
+    pub(crate) fn next_rotation(&self, opened: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.interval.map(|interval| interval.next_after(opened))
+    }
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        RotationPolicy::size(DEFAULT_MAX_FILE_SIZE)
+    }
+}
+
+impl RotationInterval {
+    fn next_after(self, time: DateTime<Local>) -> DateTime<Local> {
+        let local = time.naive_local();
+        let next = match self {
+            RotationInterval::Hourly => {
+                let hour = NaiveTime::from_hms_opt(local.hour(), 0, 0).unwrap();
+                local.date().and_time(hour) + Duration::hours(1)
+            }
+            RotationInterval::Daily => return time + Duration::days(1),
+            RotationInterval::Midnight => (local.date() + Duration::days(1)).and_time(NaiveTime::MIN),
+        };
+        // A DST gap can swallow the exact instant; rotate one period later in that case
+        next.and_local_timezone(Local).earliest().unwrap_or_else(|| match self {
+            RotationInterval::Hourly => time + Duration::hours(1),
+            _ => time + Duration::days(1),
+        })
+    }
+}
+
+
+// Which rotated files (`app.log.<timestamp>`, possibly compressed) are kept. Applied after every
+// rotation and when the file sink starts; the file being written is never removed. Without any
+// limit (the default) nothing is deleted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_files: Option<usize>,       // Keep at most this many rotated files, newest first
+    pub max_total_size: Option<u64>,    // ... as long as together they stay under this many bytes
+    pub max_age: Option<time::Duration>,  // ... and were last written to within this long
+}
+
+impl RetentionPolicy {
+    pub const fn keep_all() -> Self {
+        RetentionPolicy { max_files: None, max_total_size: None, max_age: None }
+    }
+
+    pub const fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    pub const fn with_max_total_size(mut self, max_total_size: u64) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    pub const fn with_max_age(mut self, max_age: time::Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub(crate) fn is_keep_all(&self) -> bool {
+        *self == RetentionPolicy::keep_all()
+    }
+
+    // Deletes the rotated files of `path` the policy no longer keeps, returning them with their size.
+    // Files that cannot be removed are skipped and reported on stderr.
+    pub(crate) fn prune(&self, path: &str) -> io::Result<Vec<(PathBuf, u64)>> {
+        if self.is_keep_all() {
+            return Ok(Vec::new());
+        }
+
+        let now = SystemTime::now();
+        let mut total = 0;
+        let mut removed = Vec::new();
+        for (index, (file, size, modified)) in rotated_files(path)?.into_iter().enumerate() {
+            let too_many = self.max_files.is_some_and(|max| index >= max);
+            let too_large = self.max_total_size.is_some_and(|max| total + size > max);
+            let too_old = self.max_age.is_some_and(|max| now.duration_since(modified).is_ok_and(|age| age > max));
+            if !(too_many || too_large || too_old) {
+                total += size;
+                continue;
+            }
+            match fs::remove_file(&file) {
+                Ok(()) => removed.push((file, size)),
+                Err(e) => eprintln!("mlog: failed to remove old log file {}: {}", file.display(), e),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+// Rotated files of `path`, newest first, with their size and mtime
+fn rotated_files(path: &str) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let path = Path::new(path);
+    let (Some(base), dir) = (path.file_name().and_then(|name| name.to_str()), path.parent()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match dir {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(key) = name.to_str().and_then(|name| rotation_key(base, name)) else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && !being_compressed(&entry.path()) {
+            files.push((key, entry.path(), metadata.len(), metadata.modified()?));
+        }
+    }
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(files.into_iter().map(|(_, file, size, modified)| (file, size, modified)).collect())
+}
+
+// Whether a compressor (of this or another process) is working on `file`: its `.gz.tmp` / `.zst.tmp`
+// is still being written, or the compressed copy is complete and `file` is about to be removed.
+// Pruning leaves such a file to the compressor; the compressed copy is counted once it exists.
+fn being_compressed(file: &Path) -> bool {
+    [".gz", ".zst"].iter().any(|extension| {
+        let mut compressed = file.as_os_str().to_owned();
+        compressed.push(extension);
+        let mut partial = compressed.clone();
+        partial.push(".tmp");
+        let writing = fs::metadata(&partial)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().map_or(true, |age| age < STALE_PARTIAL_AGE));
+        writing || Path::new(&compressed).exists()
+    })
+}
+
+// For a file named `base.YYYYmmddHHMMSS.fff[.N][.gz|.zst]` (what `FileSink::rotate` and the compressor
+// produce), the timestamp and counter to order it by; None for anything else, including the
+// compressor's partial `.gz.tmp` / `.zst.tmp` output
+fn rotation_key(base: &str, name: &str) -> Option<(String, u32)> {
+    if name.ends_with(".tmp") {
+        return None;
+    }
+    let rest = name.strip_prefix(base)?.strip_prefix('.')?;
+    let rest = rest.strip_suffix(".gz").or_else(|| rest.strip_suffix(".zst")).unwrap_or(rest);
+
+    let (stamp, counter) = match rest.get(18..) {
+        Some("") => (rest, 0),
+        Some(counter) => (&rest[..18], counter.strip_prefix('.')?.parse().ok()?),
+        None => return None,
+    };
+    if !stamp.is_ascii() {
+        return None;
+    }
+    let (seconds, millis) = stamp.split_at(14);
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if digits(seconds) && millis.len() == 4 && millis.starts_with('.') && digits(&millis[1..]) {
+        Some((stamp.to_string(), counter))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,504 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::mem;
+use chrono::{DateTime, Local};
+
+use crate::color::{strip_ansi, ColorChoice, ColorDepth};
+use crate::compress::Compressor;
+use crate::{Compression, LevelSet, LogLevel, LogRecord, RetentionPolicy, RotationPolicy, Value, CONSOLE_COLOR_RESET};
+
+
+// Turns a record into the line a sink writes
+pub trait Formatter: Send + Sync {
+    fn format(&self, record: &LogRecord) -> String;
+}
+
+impl<F> Formatter for F
+where
+    F: Fn(&LogRecord) -> String + Send + Sync,
+{
+    fn format(&self, record: &LogRecord) -> String {
+        self(record)
+    }
+}
+
+
+// An output destination. `line` is the record already run through the sink's formatter;
+// sinks that want the raw data (structured outputs) can ignore it and use `record` instead.
+pub trait Sink: Send {
+    fn write(&mut self, record: &LogRecord, line: &str) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Called when the sink is attached to a logger and when the logger shuts down (or the sink is removed)
+    fn session_start(&mut self, _application_name: &str, _time: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn session_end(&mut self, _time: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+// A sink plus the levels and formatter it is registered with. The sink sits behind a Mutex
+// so LogConfig stays shareable between threads without requiring `Sink: Sync`.
+pub struct SinkConfig {
+    pub(crate) sink: Mutex<Box<dyn Sink>>,
+    pub levels: LevelSet,                     // Levels this sink receives (default: all)
+    pub formatter: Option<Box<dyn Formatter>>, // Falls back to the logger's default formatter
+}
+
+impl SinkConfig {
+    pub fn new<S: Sink + 'static>(sink: S) -> Self {
+        SinkConfig {
+            sink: Mutex::new(Box::new(sink)),
+            levels: LevelSet::ALL,
+            formatter: None,
+        }
+    }
+
+    // `level` and everything more severe
+    pub fn with_level(mut self, level: LogLevel) -> Self {
+        self.levels = LevelSet::at_least(level);
+        self
+    }
+
+    // An exact set, e.g. `LevelSet::only(&[LogLevel::Crit, LogLevel::Success])`
+    pub fn with_levels(mut self, levels: LevelSet) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn with_formatter<F: Formatter + 'static>(mut self, formatter: F) -> Self {
+        self.formatter = Some(Box::new(formatter));
+        self
+    }
+}
+
+// Handle returned by `Logger::add_sink`, used to remove the sink again
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SinkId(pub(crate) u64);
+
+
+// Writes to stdout (the `console_flag` output). When colors are enabled each line is shown in
+// the record's console color, adapted to the terminal's color depth; otherwise no escape codes are written.
+pub struct ConsoleSink {
+    color: Option<ColorDepth>,  // None when colors are off
+}
+
+impl ConsoleSink {
+    pub fn new() -> Self {
+        ConsoleSink::with_color(ColorChoice::Auto)
+    }
+
+    pub fn with_color(choice: ColorChoice) -> Self {
+        let color = if choice.enabled_for_stdout() { Some(ColorDepth::detect()) } else { None };
+        ConsoleSink { color }
+    }
+
+    // Skips terminal detection, e.g. when stdout is known to support a given depth
+    pub fn with_color_depth(depth: ColorDepth) -> Self {
+        ConsoleSink { color: Some(depth) }
+    }
+}
+
+impl Default for ConsoleSink {
+    fn default() -> Self {
+        ConsoleSink::new()
+    }
+}
+
+impl Sink for ConsoleSink {
+    fn write(&mut self, record: &LogRecord, line: &str) -> io::Result<()> {
+        // writeln! rather than println!, which panics on a closed stdout (e.g. `app | head`)
+        let mut stdout = io::stdout().lock();
+        match self.color {
+            Some(depth) => writeln!(stdout, "{}{}{}", depth.adapt(record.color), line, CONSOLE_COLOR_RESET),
+            None => writeln!(stdout, "{}", strip_ansi(line)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);       // how often FileSink checks its file still exists
+const ROTATION_RETRY_INTERVAL: Duration = Duration::from_secs(60);  // wait after a failed rotation before trying again
+
+
+// Writes to any `impl Write + Send`: a socket, a pipe, an in-memory buffer...
+pub struct WriterSink<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        WriterSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> Sink for WriterSink<W> {
+    fn write(&mut self, _record: &LogRecord, line: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+// Appends to a `.log` file, writes session banners and rotates it according to its RotationPolicy
+// (by default once it would grow past 10 MB). Rotation is checked on every write, against a byte
+// count kept by the sink. Rotated files can be compressed on a background thread, and old ones
+// are deleted according to the RetentionPolicy, with a note in the log for each removed file.
+// Escape codes are stripped so the file stays plain text for grep and log shippers.
+//
+// After a failed write or flush, and when the file (or its directory) has been deleted, the file
+// is opened again before the next write, so logging resumes once the path is writable. A failed
+// rotation keeps the current file and is reported by the next flush.
+pub struct FileSink {
+    path: String,
+    writer: BufWriter<File>,
+    session_formatter: Option<Box<dyn Formatter>>,  // Session start/end as records instead of banners
+    rotation: RotationPolicy,
+    size: u64,                                      // Bytes in the current file, written or buffered
+    opened: DateTime<Local>,                        // Start of the current file (its mtime if it was left by an earlier run)
+    next_rotation: Option<DateTime<Local>>,         // Due time of time-based rotation
+    compressor: Compressor,                         // Compresses rotated files off the write path
+    retention: RetentionPolicy,                     // Which rotated files to keep
+    fresh_per_session: bool,                        // Move the previous session's file aside on start
+    failed: bool,                                   // A write or flush failed, reopen before the next write
+    checked_at: Instant,                            // Last check that the file still exists
+    deferred_error: Option<io::Error>,              // Failed rotation, returned by the next flush
+    rotation_retry: Option<Instant>,                // No rotation attempt before this, after a failure
+}
+
+impl FileSink {
+    // `.log` is appended to the path if it is missing. Missing parent directories are created.
+    pub fn new(path: &str) -> io::Result<Self> {
+        let path = if path.ends_with(".log") {
+            path.to_string()
+        } else {
+            format!("{}.log", path)
+        };
+        if let Some(dir) = Path::new(&path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = open_append(&path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        // A file from an earlier run is due when its last write's period ends, not a period after the restart
+        let opened = match metadata.modified() {
+            Ok(modified) if size > 0 => DateTime::from(modified),
+            _ => Local::now(),
+        };
+        let rotation = RotationPolicy::default();
+        Ok(FileSink {
+            path,
+            writer: BufWriter::new(file),
+            session_formatter: None,
+            rotation,
+            size,
+            opened,
+            next_rotation: rotation.next_rotation(opened),
+            compressor: Compressor::new(Compression::None),
+            retention: RetentionPolicy::keep_all(),
+            fresh_per_session: false,
+            failed: false,
+            checked_at: Instant::now(),
+            deferred_error: None,
+            rotation_retry: None,
+        })
+    }
+
+    pub fn with_rotation(mut self, rotation: RotationPolicy) -> Self {
+        self.rotation = rotation;
+        self.next_rotation = rotation.next_rotation(self.opened);
+        self
+    }
+
+    // Rotated files are compressed in the background, e.g. to `app.log.<timestamp>.gz`.
+    // The uncompressed file is only removed once its compressed copy is complete.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compressor = Compressor::new(compression);
+        self
+    }
+
+    // Old rotated files are pruned when the sink is attached and after every rotation
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    // Each session starts with an empty file: a non-empty file from an earlier run is rotated out
+    // (and compressed / pruned like any rotated file) when the session starts
+    pub fn with_fresh_file_per_session(mut self) -> Self {
+        self.fresh_per_session = true;
+        self
+    }
+
+    // Points a symlink at `link` to this file (e.g. `logs/current.log`), replacing an older link, so
+    // tools can follow the active file whatever its name. A regular file at `link` is left alone
+    // and reported as an error.
+    pub fn with_current_link(self, link: &str) -> io::Result<Self> {
+        let link = Path::new(link);
+        if let Some(dir) = link.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        if fs::symlink_metadata(link).is_ok_and(|metadata| !metadata.file_type().is_symlink()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a symlink", link.display()),
+            ));
+        }
+
+        // Relative within the same directory, so the link survives moving the directory
+        let file = Path::new(&self.path);
+        let target = if file.parent() == link.parent() {
+            PathBuf::from(file.file_name().unwrap())
+        } else {
+            fs::canonicalize(file)?
+        };
+
+        // Created under a temporary name and renamed over the old link, so it is never missing
+        let partial = PathBuf::from(format!("{}.tmp", link.display()));
+        let _ = fs::remove_file(&partial);
+        symlink(&target, &partial)?;
+        fs::rename(&partial, link)?;
+        Ok(self)
+    }
+
+    // Writes session start/end as records through `formatter` (e.g. JSON lines) instead of ASCII banners.
+    // The records carry an `event` field set to `session_start` / `session_end`.
+    pub fn with_session_records<F: Formatter + 'static>(mut self, formatter: F) -> Self {
+        self.session_formatter = Some(Box::new(formatter));
+        self
+    }
+
+    fn write_session_record(&mut self, message: &str, event: &str) -> io::Result<bool> {
+        self.write_event_record(message, event, Vec::new())
+    }
+
+    // The sink's own events (session, rotation, pruning) as records, when it has a session formatter
+    fn write_event_record(&mut self, message: &str, event: &str, fields: Vec<(String, Value)>) -> io::Result<bool> {
+        let Some(ref formatter) = self.session_formatter else {
+            return Ok(false);
+        };
+        let mut record = LogRecord::new(LogLevel::Info, "mlog", message.to_string(), LogLevel::Info.color());
+        record.fields.push(("event".to_string(), Value::Str(event.to_string())));
+        record.fields.extend(fields);
+        let line = formatter.format(&record);
+        self.write_line(&line)?;
+        self.writer.flush()?;
+        Ok(true)
+    }
+
+    fn write_session_banner(&mut self, application_name: &str, time: &str) -> io::Result<()> {
+        // Format the session start text
+        let session_text = format!(
+            "///////// {} : Session Started at {} /////////",
+            application_name,
+            time
+        );
+
+        // Calculate the length of the `-` line based on the session text length
+        let separator_line = "-".repeat(session_text.len());
+
+        // Write the session start with dynamic separator lines
+        self.write_line(&format!("\n\n{}\n{}\n{}\n", separator_line, session_text, separator_line))?;
+        self.writer.flush()
+    }
+
+    // Every write goes through here so `size` stays exact
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Size of the current file in bytes, including data not yet flushed
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // Rotates before writing `incoming` bytes at `now` if the policy says so
+    fn rotate_if_due(&mut self, now: DateTime<Local>, incoming: u64) -> io::Result<()> {
+        if let Some(retry) = self.rotation_retry {
+            if Instant::now() < retry {
+                return Ok(());
+            }
+            self.rotation_retry = None;
+        }
+        let time_due = self.next_rotation.is_some_and(|due| now >= due);
+        if time_due || self.rotation.size_exceeded(self.size, incoming) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    // Renames the current file to `path.<timestamp>` and starts a new one, whatever the policy
+    pub fn rotate(&mut self) -> io::Result<()> {
+        let rotated_path = self.archive()?;
+
+        // Write log rotation message
+        if !self.write_session_record("Log rotated", "log_rotated")? {
+            let marker = format!("\n\n--- Log rotated at {} ---", Local::now().format("%Y-%m-%d %H:%M:%S%.3f"));
+            self.write_line(&marker)?;
+            self.writer.flush()?;
+        }
+        self.prune_and_compress(Some(rotated_path))
+    }
+
+    // Moves the current file aside as `path.<timestamp>` and reopens an empty one, returning the new name
+    fn archive(&mut self) -> io::Result<String> {
+        self.writer.flush()?;
+
+        // Millisecond timestamp, with a counter in the unlikely case the name is already taken
+        let stamp = format!("{}.{}", self.path, Local::now().format("%Y%m%d%H%M%S%.3f"));
+        let mut rotated_path = stamp.clone();
+        let mut n = 1;
+        while Path::new(&rotated_path).exists() {
+            rotated_path = format!("{}.{}", stamp, n);
+            n += 1;
+        }
+        fs::rename(&self.path, &rotated_path)?;
+
+        self.writer = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        self.opened = Local::now();
+        self.next_rotation = self.rotation.next_rotation(self.opened);
+        Ok(rotated_path)
+    }
+
+    // Reopens the file after a failure, or when it has been deleted while open (writes to it would
+    // succeed but be lost). The deletion check runs at most once per FILE_CHECK_INTERVAL.
+    fn ensure_open(&mut self) -> io::Result<()> {
+        if !self.failed {
+            if self.checked_at.elapsed() < FILE_CHECK_INTERVAL {
+                return Ok(());
+            }
+            self.checked_at = Instant::now();
+            if Path::new(&self.path).exists() {
+                return Ok(());
+            }
+        }
+        self.reopen().inspect_err(|_| self.failed = true)
+    }
+
+    // Opens the file again, recreating its directory, and carries over data still buffered for the old handle
+    fn reopen(&mut self) -> io::Result<()> {
+        if let Some(dir) = Path::new(&self.path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = open_append(&self.path)?;
+        let size = file.metadata()?.len();
+
+        // into_parts does not flush, so nothing goes to the old handle twice
+        let (_, pending) = mem::replace(&mut self.writer, BufWriter::new(file)).into_parts();
+        let pending = pending.unwrap_or_else(|e| e.into_inner());
+        self.writer.write_all(&pending)?;  // fits, the new buffer has the same capacity
+        self.size = size + pending.len() as u64;
+        self.failed = false;
+        self.checked_at = Instant::now();
+        Ok(())
+    }
+
+    // Pruned first, so a file the policy drops right away is not compressed for nothing
+    fn prune_and_compress(&mut self, rotated_path: Option<String>) -> io::Result<()> {
+        let removed = self.prune()?;
+        if let Some(rotated_path) = rotated_path {
+            if !removed.iter().any(|file| file.as_os_str() == rotated_path.as_str()) {
+                self.compressor.submit(PathBuf::from(rotated_path));
+            }
+        }
+        Ok(())
+    }
+
+    // Applies the retention policy and notes each removed file in the log
+    fn prune(&mut self) -> io::Result<Vec<PathBuf>> {
+        let removed = self.retention.prune(&self.path)?;
+        for (file, size) in &removed {
+            let name = file.display().to_string();
+            let message = format!("Removed old log file {} ({} bytes)", name, size);
+            let fields = vec![("file".to_string(), Value::Str(name)), ("bytes".to_string(), Value::U64(*size))];
+            if !self.write_event_record(&message, "log_removed", fields)? {
+                self.write_line(&format!("--- {} ---", message))?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(removed.into_iter().map(|(file, _)| file).collect())
+    }
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+impl Sink for FileSink {
+    fn write(&mut self, record: &LogRecord, line: &str) -> io::Result<()> {
+        let line = strip_ansi(line);
+        self.ensure_open()?;
+        if let Err(e) = self.rotate_if_due(record.time, line.len() as u64 + 1) {
+            // Better a file past its limit than lost records
+            self.rotation_retry = Some(Instant::now() + ROTATION_RETRY_INTERVAL);
+            self.deferred_error = Some(e);
+        }
+        self.write_line(&line).inspect_err(|_| self.failed = true)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().inspect_err(|_| self.failed = true)?;
+        match self.deferred_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn session_start(&mut self, application_name: &str, time: &str) -> io::Result<()> {
+        // A file whose rotation fell due while the application was not running is rotated right away
+        let overdue = self.next_rotation.is_some_and(|due| Local::now() >= due);
+        let previous = if (self.fresh_per_session || overdue) && self.size > 0 { Some(self.archive()?) } else { None };
+        if !self.write_session_record("Session started", "session_start")? {
+            self.write_session_banner(application_name, time)?;
+        }
+        // Also catches files left over from earlier runs
+        self.prune_and_compress(previous)
+    }
+
+    fn session_end(&mut self, time: &str) -> io::Result<()> {
+        self.ensure_open()?;
+        if self.write_session_record("Session ended", "session_end")? {
+            return Ok(());
+        }
+        self.write_line(&format!("\n------ Session Ended at {} ------ \n", time))?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,176 @@
+// `tracing` integration (cargo feature "tracing"): a `tracing_subscriber::Layer` that turns events into
+// mlog records. The enclosing spans are rendered in front of the message the way tracing's own fmt
+// layer does it, e.g. `request{id=7}:db: query done`, and the event's fields become record fields.
+use std::fmt::{self, Write};
+use std::sync::Arc;
+
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::subscriber::Interest;
+use tracing_core::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::value::write_fields;
+use crate::{with_logger_opt, LogLevel, LogRecord, Logger, Value};
+
+// Sends events to the global logger installed by `mlog::init`, or to a specific logger
+#[derive(Clone, Default)]
+pub struct MlogLayer {
+    logger: Option<Arc<Logger>>,  // None -> global logger
+}
+
+impl MlogLayer {
+    pub fn new() -> Self {
+        MlogLayer { logger: None }
+    }
+
+    pub fn with_logger(logger: Arc<Logger>) -> Self {
+        MlogLayer { logger: Some(logger) }
+    }
+
+    fn with_target<R>(&self, f: impl FnOnce(&Logger) -> R) -> Option<R> {
+        match &self.logger {
+            Some(logger) => Some(f(logger)),
+            None => with_logger_opt(f),
+        }
+    }
+}
+
+// tracing has no Success or Crit
+fn to_log_level(level: &Level) -> LogLevel {
+    match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        Level::TRACE => LogLevel::Trace,
+    }
+}
+
+// Span fields rendered as `key=value`, kept in the span's extensions
+struct SpanFields(String);
+
+// Collects the `message` field and keeps every other field as a typed value
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(String, Value)>,
+}
+
+impl FieldVisitor {
+    fn push(&mut self, field: &Field, value: Value) {
+        self.fields.push((field.name().to_string(), value));
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Value::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Value::U64(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, Value::F64(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, Value::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.push(field, Value::Str(value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            self.push(field, Value::Str(format!("{:?}", value)));
+        }
+    }
+}
+
+impl<S> Layer<S> for MlogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    // Never cached as `never`: the level and filter can change at runtime, and a `false` from
+    // `enabled` would also hide the event from other layers. Events are filtered in `on_event`.
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        let mut fields = String::new();
+        write_fields(&mut fields, &visitor.fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<SpanFields>() {
+                Some(SpanFields(fields)) => {
+                    if !fields.is_empty() {
+                        fields.push(' ');
+                    }
+                    write_fields(fields, &visitor.fields);
+                }
+                None => {
+                    let mut fields = String::new();
+                    write_fields(&mut fields, &visitor.fields);
+                    extensions.insert(SpanFields(fields));
+                }
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = to_log_level(metadata.level());
+        if !self.with_target(|logger| logger.enabled_for(level, metadata.target())).unwrap_or(false) {
+            return;
+        }
+
+        let mut message = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                message.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(message, "{{{}}}", fields);
+                    }
+                }
+                message.push(':');
+            }
+            if !message.is_empty() {
+                message.push(' ');
+            }
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        message.push_str(&visitor.message);
+
+        let mut record = LogRecord::new(level, metadata.target(), message, level.color());
+        record.fields = visitor.fields;
+        record.module_path = metadata.module_path().map(str::to_string);
+        record.file = metadata.file().map(str::to_string);
+        record.line = metadata.line();
+        self.with_target(|logger| logger.log_unfiltered(record));  // checked above
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use crate::logfmt::{write_logfmt_key, write_logfmt_value};
+
+// Typed value of a structured field, e.g. `latency_ms = 3.1` in `info!(latency_ms = 3.1; "request done")`.
+// Values captured with `%` (Display) or `?` (Debug) are kept as their rendered text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => fmt::Display::fmt(v, f),
+            Value::I64(v) => fmt::Display::fmt(v, f),
+            Value::U64(v) => fmt::Display::fmt(v, f),
+            Value::F64(v) => fmt::Display::fmt(v, f),
+            Value::Str(v) => f.pad(v),
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($variant:ident: $($ty:ty),*) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    Value::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(Bool: bool);
+impl_from!(I64: i8, i16, i32, i64);
+impl_from!(U64: u8, u16, u32, u64);
+impl_from!(F64: f32, f64);
+impl_from!(Str: String, &str, char);
+
+impl From<isize> for Value {
+    fn from(value: isize) -> Self {
+        Value::I64(value as i64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::U64(value as u64)
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Self {
+        Value::Str(value.clone())
+    }
+}
+
+// Renders fields as `key=value` pairs separated by spaces, quoting values the way logfmt does
+// (empty, or containing spaces, quotes, '=' or control characters)
+pub(crate) fn write_fields(out: &mut String, fields: &[(String, Value)]) {
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write_logfmt_key(out, key);
+        out.push('=');
+        write_logfmt_value(out, &value.to_string());
+    }
+}
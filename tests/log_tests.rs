@@ -1,6 +1,7 @@
 
 use mlog::*;
-use std::sync::Arc;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
 use std::fs;
 use std::thread;
 use std::time::Duration;
@@ -16,9 +17,92 @@ mod tests {
     fn get_test_log_path() -> String {
         format!("test_log_{}.log", Local::now().timestamp())
     }
+
+    // Unique per-test log file under cargo's scratch dir, so parallel tests never share a file
+    fn scratch_log_path(name: &str) -> String {
+        let path = format!("{}/{}_{}.log", env!("CARGO_TARGET_TMPDIR"), name, Local::now().timestamp_nanos_opt().unwrap());
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // In-memory `Write` target that tests can read back while the logger still owns a clone
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    // User-defined sink that keeps the raw records
+    struct CollectSink(Arc<Mutex<Vec<LogRecord>>>);
+
+    impl Sink for CollectSink {
+        fn write(&mut self, record: &LogRecord, _line: &str) -> io::Result<()> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    // Fails the first `failures` writes, then keeps the messages it is given
+    struct FlakySink {
+        failures: usize,
+        written: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink for FlakySink {
+        fn write(&mut self, record: &LogRecord, _line: &str) -> io::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.written.lock().unwrap().push(record.message.clone());
+            Ok(())
+        }
+    }
+
+    // Panics when asked to write `message`
+    struct PanickingSink(&'static str);
+
+    impl Sink for PanickingSink {
+        fn write(&mut self, record: &LogRecord, _line: &str) -> io::Result<()> {
+            if record.message == self.0 {
+                panic!("sink cannot write {:?}", record.message);
+            }
+            Ok(())
+        }
+    }
+
+    // Tests that go through the global logger (`mlog::init` and the macros) take turns
+    static GLOBAL_LOGGER: Mutex<()> = Mutex::new(());
+
+    // Tests that change NO_COLOR, CLICOLOR_FORCE or TERM take turns
+    static COLOR_VARS: Mutex<()> = Mutex::new(());
+
+    fn read_log_lines(path: &str, marker: &str) -> Vec<String> {
+        fs::read_to_string(path)
+            .expect("Failed to read log file")
+            .lines()
+            .filter(|line| line.contains(marker))
+            .map(str::to_string)
+            .collect()
+    }
+
     #[test]
     #[allow(unreachable_code)]
     fn test_default() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
 
         let log_config = LogConfig {
             time_format : "%H:%M:%S".to_string(),
@@ -35,9 +119,1877 @@ mod tests {
         error!("Error Code : ({})", 2);
         crit!("This is a critical error :/");
         mlog::log_flush!();
-        shutdown();
+        shutdown().unwrap();
+        }
+
+    #[test]
+    fn test_global_set_level() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        assert_eq!(mlog::level(), None);
+        let records = Arc::new(Mutex::new(Vec::new()));
+        mlog::init(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        assert_eq!(mlog::level(), Some(LogLevel::Info));
+
+        let log_all = || with_logger(|logger| {
+            logger.log(LogLevel::Info, "info", CONSOLE_COLOR_INFO);
+            logger.log(LogLevel::Warn, "warn", CONSOLE_COLOR_WARN);
+        });
+        log_all();
+        mlog::set_level(LogLevel::Warn);
+        assert_eq!(mlog::level(), Some(LogLevel::Warn));
+        log_all();
+        shutdown().unwrap();
+        assert_eq!(mlog::level(), None);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.iter().map(|r| r.message.as_str()).collect::<Vec<_>>(), ["info", "warn", "warn"]);
+    }
+
+    #[test]
+    fn test_async_queue_many_producers() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 500;
+
+        let path = scratch_log_path("async_many_producers");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            multi_threaded_flag: true,
+            buffer_capacity: THREADS * PER_THREAD,
+            ..Default::default()
+        });
+
+        let handles: Vec<_> = (0..THREADS).map(|t| {
+            let logger = Arc::clone(&logger);
+            thread::spawn(move || {
+                for i in 0..PER_THREAD {
+                    logger.log(LogLevel::Info, &format!("stress t{} n{} end", t, i), CONSOLE_COLOR_INFO);
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        logger.flush();
+
+        // Every record must arrive exactly once and untorn
+        let lines = read_log_lines(&path, "stress t");
+        assert_eq!(lines.len(), THREADS * PER_THREAD);
+        let mut seen = std::collections::HashSet::new();
+        for line in &lines {
+            let start = line.find("stress t").unwrap();
+            let end = line.find(" end").unwrap();
+            assert!(seen.insert(line[start..end].to_string()), "duplicate record: {}", line);
+        }
+        for t in 0..THREADS {
+            for i in 0..PER_THREAD {
+                assert!(seen.contains(&format!("stress t{} n{}", t, i)));
+            }
+        }
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_async_queue_concurrent_drain() {
+        const THREADS: usize = 4;
+        const PER_THREAD: usize = 2000;
+
+        // Queue drained by another thread while the producers are still pushing
+        let path = scratch_log_path("async_concurrent_drain");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            multi_threaded_flag: true,
+            buffer_capacity: THREADS * PER_THREAD,
+            ..Default::default()
+        });
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let drainer = {
+            let logger = Arc::clone(&logger);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while !done.load(std::sync::atomic::Ordering::Acquire) {
+                    logger.flush();
+                    thread::sleep(Duration::from_micros(50));
+                }
+            })
+        };
+
+        let handles: Vec<_> = (0..THREADS).map(|t| {
+            let logger = Arc::clone(&logger);
+            thread::spawn(move || {
+                for i in 0..PER_THREAD {
+                    logger.log(LogLevel::Warn, &format!("drain t{} n{} end", t, i), CONSOLE_COLOR_WARN);
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        done.store(true, std::sync::atomic::Ordering::Release);
+        drainer.join().unwrap();
+        logger.flush();
+
+        let lines = read_log_lines(&path, "drain t");
+        let unique: std::collections::HashSet<_> = lines.iter().collect();
+        assert_eq!(lines.len(), THREADS * PER_THREAD);
+        assert_eq!(unique.len(), lines.len(), "duplicated records");
+        assert!(lines.iter().all(|line| line.ends_with(" end")), "torn record");
+        logger.shutdown().unwrap();
+    }
+
+    fn overflow_logger(name: &str, policy: OverflowPolicy) -> (Arc<Logger>, String) {
+        let path = scratch_log_path(name);
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            buffer_capacity: 4,
+            overflow_policy: policy,
+            max_latency: Duration::from_secs(60),  // keep the writer asleep unless woken
+            ..Default::default()
+        });
+        (logger, path)
+    }
+
+    #[test]
+    fn test_overflow_drop_newest() {
+        let (logger, path) = overflow_logger("overflow_drop_newest", OverflowPolicy::DropNewest);
+        for i in 0..10 {
+            logger.log(LogLevel::Info, &format!("overflow n{} end", i), CONSOLE_COLOR_INFO);
+        }
+        assert_eq!(logger.dropped_count(), 6);
+        logger.flush();
+
+        let lines = read_log_lines(&path, "overflow n");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("overflow n0 end") && lines[3].contains("overflow n3 end"));
+        assert_eq!(read_log_lines(&path, "6 messages dropped since").len(), 1);
+
+        // The summary is only written once per episode
+        logger.flush();
+        assert_eq!(read_log_lines(&path, "messages dropped since").len(), 1);
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_overflow_drop_oldest() {
+        let (logger, path) = overflow_logger("overflow_drop_oldest", OverflowPolicy::DropOldest);
+        for i in 0..10 {
+            logger.log(LogLevel::Info, &format!("overflow n{} end", i), CONSOLE_COLOR_INFO);
+        }
+        assert_eq!(logger.dropped_count(), 6);
+        logger.flush();
+
+        let lines = read_log_lines(&path, "overflow n");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("overflow n6 end") && lines[3].contains("overflow n9 end"));
+        assert_eq!(read_log_lines(&path, "6 messages dropped since").len(), 1);
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_overflow_block() {
+        let (logger, path) = overflow_logger("overflow_block", OverflowPolicy::Block);
+        for i in 0..50 {
+            logger.log(LogLevel::Info, &format!("overflow n{} end", i), CONSOLE_COLOR_INFO);
+        }
+        logger.flush();
+
+        assert_eq!(logger.dropped_count(), 0);
+        assert_eq!(read_log_lines(&path, "overflow n").len(), 50);
+        assert!(read_log_lines(&path, "messages dropped").is_empty());
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_overflow_shed_by_level() {
+        let path = scratch_log_path("overflow_shed_by_level");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            buffer_capacity: 8,
+            overflow_policy: OverflowPolicy::ShedByLevel,
+            max_latency: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        // Info may only fill half of the queue
+        for i in 0..8 {
+            logger.log(LogLevel::Info, &format!("shed info n{}", i), CONSOLE_COLOR_INFO);
+        }
+        assert_eq!(logger.dropped_count(), 4);
+
+        // Crit still gets in, and is never dropped even past capacity
+        for i in 0..8 {
+            logger.log(LogLevel::Crit, &format!("shed crit n{}", i), CONSOLE_BG_COLOR_RED);
         }
-    
+        assert_eq!(logger.dropped_count(), 4);
+        logger.flush();
+
+        assert_eq!(read_log_lines(&path, "shed info").len(), 4);
+        assert_eq!(read_log_lines(&path, "shed crit").len(), 8);
+        logger.shutdown().unwrap();
+    }
+
+    // Polls the log file until `count` lines contain `marker`, without flushing from the test thread
+    fn wait_for_lines(path: &str, marker: &str, count: usize) -> bool {
+        for _ in 0..200 {
+            if fs::read_to_string(path).map(|s| s.matches(marker).count() >= count).unwrap_or(false) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    fn writer_logger(name: &str, max_batch_size: usize, max_latency: Duration) -> (Arc<Logger>, String) {
+        let path = scratch_log_path(name);
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            max_batch_size,
+            max_latency,
+            ..Default::default()
+        });
+        (logger, path)
+    }
+
+    #[test]
+    fn test_async_writer_crit_is_immediate() {
+        let (logger, path) = writer_logger("writer_crit", 64, Duration::from_secs(60));
+        logger.log(LogLevel::Info, "queued info", CONSOLE_COLOR_INFO);
+        logger.log(LogLevel::Crit, "urgent crit", CONSOLE_BG_COLOR_RED);
+
+        // Crit wakes the writer, which also takes everything queued before it
+        assert!(wait_for_lines(&path, "urgent crit", 1));
+        assert!(wait_for_lines(&path, "queued info", 1));
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_async_writer_wakes_on_full_batch() {
+        let (logger, path) = writer_logger("writer_batch", 16, Duration::from_secs(60));
+        for i in 0..16 {
+            logger.log(LogLevel::Info, &format!("batch n{}", i), CONSOLE_COLOR_INFO);
+        }
+        assert!(wait_for_lines(&path, "batch n", 16));
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_async_writer_max_latency() {
+        let (logger, path) = writer_logger("writer_latency", 1024, Duration::from_millis(20));
+        logger.log(LogLevel::Info, "lonely record", CONSOLE_COLOR_INFO);
+        assert!(wait_for_lines(&path, "lonely record", 1));
+        logger.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_drains_and_joins_writer() {
+        let (logger, path) = writer_logger("shutdown_join", 1024, Duration::from_secs(60));
+        for i in 0..100 {
+            logger.log(LogLevel::Info, &format!("pending n{}", i), CONSOLE_COLOR_INFO);
+        }
+        logger.shutdown().unwrap();
+
+        // The writer has exited and released the logger
+        assert_eq!(Arc::strong_count(&logger), 1);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.matches("pending n").count(), 100);
+        assert!(contents.rfind("pending n99").unwrap() < contents.find("Session Ended").unwrap());
+    }
+
+    #[test]
+    fn test_shutdown_timeout_reports_pending() {
+        const COUNT: usize = 100_000;
+
+        // Zero deadline and a backlog big enough that the writer cannot drain it in one time slice
+        let path = scratch_log_path("shutdown_timeout");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            async_flag: true,
+            buffer_capacity: COUNT,
+            max_batch_size: COUNT * 2,  // never woken early, everything is left for shutdown
+            max_latency: Duration::from_secs(60),
+            shutdown_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+        for i in 0..COUNT {
+            logger.log(LogLevel::Info, &format!("pending n{}", i), CONSOLE_COLOR_INFO);
+        }
+
+        let err = logger.shutdown().unwrap_err();
+        assert!(err.pending > 0 && err.pending <= COUNT);
+
+        // The detached writer still drains everything
+        assert!(wait_for_lines(&path, "pending n", COUNT));
+    }
+
+    #[test]
+    fn test_drop_without_shutdown_drains_queue() {
+        let (logger, path) = writer_logger("drop_drain", 1024, Duration::from_secs(60));
+        for i in 0..10 {
+            logger.log(LogLevel::Info, &format!("dropped logger n{}", i), CONSOLE_COLOR_INFO);
+        }
+        drop(logger);
+        assert_eq!(read_log_lines(&path, "dropped logger n").len(), 10);
+    }
+
+    #[test]
+    fn test_sinks_with_own_level_and_formatter() {
+        let everything = SharedBuffer::default();
+        let errors = SharedBuffer::default();
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            sinks: vec![
+                SinkConfig::new(WriterSink::new(everything.clone())),
+                SinkConfig::new(WriterSink::new(errors.clone()))
+                    .with_level(LogLevel::Error)
+                    .with_formatter(|record: &LogRecord| format!("{:?}: {}", record.level, record.message)),
+            ],
+            ..Default::default()
+        });
+
+        logger.log(LogLevel::Info, "sink info", CONSOLE_COLOR_INFO);
+        logger.log(LogLevel::Warn, "sink warn", CONSOLE_COLOR_WARN);
+        logger.log(LogLevel::Error, "sink error", CONSOLE_COLOR_PINK);
+        logger.log(LogLevel::Crit, "sink crit", CONSOLE_BG_COLOR_RED);
+        logger.shutdown().unwrap();
+
+        let everything = everything.contents();
+        assert_eq!(everything.lines().count(), 4);
+        assert!(everything.contains("] INFO    sink info\n"));
+        assert_eq!(errors.contents(), "Error: sink error\nCrit: sink crit\n");
+    }
+
+    #[test]
+    fn test_add_and_remove_sinks_at_runtime() {
+        for async_flag in [false, true] {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                async_flag,
+                max_latency: Duration::from_secs(60),
+                ..Default::default()
+            });
+
+            logger.log(LogLevel::Info, "before add", CONSOLE_COLOR_INFO);
+            let id = logger.add_sink(SinkConfig::new(CollectSink(Arc::clone(&records))));
+            logger.log(LogLevel::Warn, "while attached", CONSOLE_COLOR_WARN);
+
+            // Anything still queued is written out before the sink is handed back
+            assert!(logger.remove_sink(id).is_some());
+            assert!(logger.remove_sink(id).is_none());
+            logger.log(LogLevel::Info, "after remove", CONSOLE_COLOR_INFO);
+            logger.shutdown().unwrap();
+
+            let records = records.lock().unwrap();
+            let messages: Vec<_> = records.iter().map(|r| r.message.as_str()).collect();
+            // In async mode "before add" was still queued when the sink was attached
+            if async_flag {
+                assert_eq!(messages, ["before add", "while attached"]);
+            } else {
+                assert_eq!(messages, ["while attached"]);
+            }
+            assert_eq!(records.last().unwrap().level, LogLevel::Warn);
+        }
+    }
+
+    // info!/warn! compile to nothing with the performance feature
+    #[cfg(not(feature = "performance"))]
+    #[test]
+    fn test_macro_structured_fields() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let text = SharedBuffer::default();
+        mlog::init(LogConfig {
+            console_flag: false,
+            sinks: vec![
+                SinkConfig::new(CollectSink(Arc::clone(&records))),
+                SinkConfig::new(WriterSink::new(text.clone())).with_formatter(PatternFormatter::new("{level} {msg}").unwrap()),
+            ],
+            ..Default::default()
+        });
+
+        let addr = "10.0.0.1";
+        let peer = Some(80);
+        let user = "ada lovelace";
+
+        info!(user_id = 42, latency_ms = 3.1, ok = true; "request done");
+        warn!(%addr, ?peer, user, retries = 2u8; "retrying {} of {}", 2, 3);
+        error!(code = %404, reason = ?"gone"; "not found");
+        info!("plain {}", "message");
+        shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let field = |key: &str, value: Value| (key.to_string(), value);
+        assert_eq!(records[0].fields, [field("user_id", Value::I64(42)), field("latency_ms", Value::F64(3.1)), field("ok", Value::Bool(true))]);
+        assert_eq!(records[1].message, "retrying 2 of 3");
+        assert_eq!(records[1].fields, [
+            field("addr", Value::Str("10.0.0.1".to_string())),
+            field("peer", Value::Str("Some(80)".to_string())),
+            field("user", Value::Str("ada lovelace".to_string())),
+            field("retries", Value::U64(2)),
+        ]);
+        assert!(records[3].fields.is_empty());
+
+        assert_eq!(text.contents(), concat!(
+            "INFO request done user_id=42 latency_ms=3.1 ok=true\n",
+            "WARN retrying 2 of 3 addr=10.0.0.1 peer=Some(80) user=\"ada lovelace\" retries=2\n",
+            "ERROR not found code=404 reason=\"\\\"gone\\\"\"\n",
+            "INFO plain message\n",
+        ));
+
+        let mut record = records[0].clone();
+        record.message = "done".to_string();
+        let fields_first = PatternFormatter::new("{fields} | {msg}").unwrap().format(&record);
+        assert_eq!(fields_first, "user_id=42 latency_ms=3.1 ok=true | done");
+    }
+
+    #[test]
+    fn test_shutdown_while_global_logger_in_use() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        mlog::init(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+
+        // The other thread still holds the logger when it is shut down; it must stay valid
+        let (entered_tx, entered_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let user = thread::spawn(move || {
+            with_logger_opt(|logger| {
+                entered_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+                logger.log(LogLevel::Info, "late", CONSOLE_COLOR_WHITE);
+            })
+        });
+        entered_rx.recv().unwrap();
+        shutdown().unwrap();
+        assert!(with_logger_opt(|_| ()).is_none());
+        release_tx.send(()).unwrap();
+        assert_eq!(user.join().unwrap(), Some(()));
+        assert_eq!(records.lock().unwrap().iter().map(|r| r.message.as_str()).collect::<Vec<_>>(), ["late"]);
+    }
+
+    #[cfg(not(feature = "performance"))]
+    #[test]
+    fn test_macros_record_callsite() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let text = SharedBuffer::default();
+        mlog::init(LogConfig {
+            console_flag: false,
+            sinks: vec![
+                SinkConfig::new(CollectSink(Arc::clone(&records))),
+                SinkConfig::new(WriterSink::new(text.clone()))
+                    .with_formatter(PatternFormatter::new("{module} {file}:{line} {msg}").unwrap()),
+            ],
+            ..Default::default()
+        });
+
+        let line = line!() + 1;
+        warn!("with callsite {}", 1);
+        error!(code = 7; "with fields");
+        shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.module_path.as_deref(), Some(module_path!()));
+            assert_eq!(record.file.as_deref(), Some(file!()));
+            assert_eq!(record.line, Some(line + i as u32));
+            assert_eq!(record.thread_id, thread::current().id());
+        }
+        assert_eq!(records[0].target, module_path!());
+        assert_eq!(text.contents().lines().next().unwrap(), format!("{} {}:{} with callsite 1", module_path!(), file!(), line));
+    }
+
+    #[test]
+    fn test_target_filter_directives() {
+        let filter = TargetFilter::parse("warn, myapp::net=error ,myapp::net::tcp=info,myapp::db=OFF", LogLevel::Info).unwrap();
+        assert!(filter.enabled_for(LogLevel::Warn, "myapp"));
+        assert!(!filter.enabled_for(LogLevel::Info, "myapp::ui"));
+        assert!(!filter.enabled_for(LogLevel::Warn, "myapp::net"));
+        assert!(filter.enabled_for(LogLevel::Error, "myapp::net::udp"));
+        assert!(filter.enabled_for(LogLevel::Info, "myapp::net::tcp::tls"));
+        assert!(filter.enabled_for(LogLevel::Warn, "myapp::network"));  // not a child of myapp::net
+        assert!(!filter.enabled_for(LogLevel::Crit, "myapp::db"));
+        assert_eq!(filter.max_level(), Some(LogLevel::Info));
+
+        // Without a bare level the config level is the default
+        let filter = TargetFilter::parse("myapp::db=crit", LogLevel::Success).unwrap();
+        assert!(filter.enabled_for(LogLevel::Success, "other"));
+        assert!(!filter.enabled_for(LogLevel::Error, "myapp::db"));
+        assert!(!filter.enabled(LogLevel::Info));
+
+        assert!(TargetFilter::parse("myapp=loud", LogLevel::Info).unwrap_err().message.contains("loud"));
+        assert!(TargetFilter::parse("=warn", LogLevel::Info).is_err());
+        assert!(TargetFilter::parse("verbose", LogLevel::Info).is_err());
+    }
+
+    #[test]
+    fn test_logger_filters_by_target() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_level: LogLevel::Crit,
+            filter: Some("myapp::net=warn,myapp::db=error".to_string()),
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        assert!(logger.enabled(LogLevel::Warn));
+        assert!(!logger.enabled(LogLevel::Success));
+
+        logger.log_with_target(LogLevel::Warn, "myapp::net::tcp", "net warn", CONSOLE_COLOR_WARN);
+        logger.log_with_target(LogLevel::Warn, "myapp::db", "db warn", CONSOLE_COLOR_WARN);
+        logger.log_with_target(LogLevel::Error, "myapp::db", "db error", CONSOLE_COLOR_PINK);
+        logger.log_with_target(LogLevel::Error, "myapp::ui", "ui error", CONSOLE_COLOR_PINK);
+        logger.log_record(LogRecord::new(LogLevel::Crit, "myapp::ui", "ui crit".to_string(), CONSOLE_BG_COLOR_RED));
+        logger.shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let messages: Vec<_> = records.iter().map(|r| r.message.as_str()).collect();
+        assert_eq!(messages, ["net warn", "db error", "ui crit"]);
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        // Only this test touches these variables (MLOG_FILTER is left alone, Logger::new reads it)
+        let vars = [ENV_LEVEL, ENV_FILE, ENV_FILE_FORMAT, ENV_FILE_LEVELS, ENV_FILE_COMPRESS, ENV_FILE_KEEP_FILES, ENV_FILE_KEEP_SIZE, ENV_FILE_KEEP_AGE, ENV_FILE_MAX_SIZE, ENV_FILE_ROTATE, ENV_CONSOLE, ENV_CONSOLE_LEVELS, ENV_COLOR, ENV_ASYNC, ENV_TIME_FORMAT, ENV_APP, ENV_ON_IO_ERROR];
+        let set = |pairs: &[(&str, &str)]| {
+            for var in vars {
+                std::env::remove_var(var);
+            }
+            for (var, value) in pairs {
+                std::env::set_var(var, value);
+            }
+        };
+
+        set(&[
+            (ENV_LEVEL, "Warn"),
+            (ENV_FILE, "logs/service"),
+            (ENV_FILE_FORMAT, "json"),
+            (ENV_FILE_LEVELS, "crit+success"),
+            (ENV_FILE_MAX_SIZE, "64 MB"),
+            (ENV_FILE_ROTATE, "Midnight"),
+            (ENV_FILE_COMPRESS, "none"),
+            (ENV_FILE_KEEP_FILES, "30"),
+            (ENV_FILE_KEEP_SIZE, "1GB"),
+            (ENV_FILE_KEEP_AGE, "2w"),
+            (ENV_CONSOLE, "off"),
+            (ENV_CONSOLE_LEVELS, "all-success"),
+            (ENV_COLOR, "never"),
+            (ENV_ASYNC, "1"),
+            (ENV_TIME_FORMAT, "%H:%M:%S%.3f"),
+            (ENV_APP, "billing"),
+            (ENV_ON_IO_ERROR, "Ignore"),
+        ]);
+        let config = LogConfig::from_env().unwrap();
+        assert_eq!(config.log_level, LogLevel::Warn);
+        assert_eq!(config.log_filepath.as_deref(), Some("logs/service"));
+        assert_eq!(config.file_format, OutputFormat::Json);
+        assert_eq!(config.file_levels, LevelSet::only(&[LogLevel::Crit, LogLevel::Success]));
+        assert_eq!(config.rotation, RotationPolicy::size(64 * 1024 * 1024).with_interval(RotationInterval::Midnight));
+        assert_eq!(config.compression, Compression::None);
+        assert_eq!(config.retention, RetentionPolicy::keep_all()
+            .with_max_files(30)
+            .with_max_total_size(1024 * 1024 * 1024)
+            .with_max_age(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert!(!config.console_flag);
+        assert_eq!(config.console_levels, LevelSet::ALL.without(LogLevel::Success));
+        assert_eq!(config.color, ColorChoice::Never);
+        assert!(config.async_flag);
+        assert_eq!(config.time_format, "%H:%M:%S%.3f");
+        assert_eq!(config.application_name, "billing");
+        assert!(matches!(config.io_error_policy, IoErrorPolicy::Ignore));
+
+        // Overlay: unset variables keep the existing values
+        set(&[(ENV_CONSOLE, "no"), (ENV_FILE, ""), (ENV_FILE_MAX_SIZE, "0")]);
+        let base = LogConfig {
+            log_level: LogLevel::Error,
+            log_filepath: Some("app.log".to_string()),
+            application_name: "base".to_string(),
+            ..Default::default()
+        };
+        let config = base.with_env().unwrap();
+        assert_eq!(config.log_level, LogLevel::Error);
+        assert_eq!(config.application_name, "base");
+        assert!(!config.console_flag);
+        assert_eq!(config.log_filepath, None);
+        assert_eq!(config.rotation, RotationPolicy::never());
+
+        for (var, value, hint) in [
+            (ENV_LEVEL, "loud", "expected one of trace, debug, info"),
+            (ENV_ASYNC, "maybe", "expected true or false"),
+            (ENV_COLOR, "rainbow", "expected always, never or auto"),
+            (ENV_FILE_FORMAT, "xml", "expected text, json or logfmt"),
+            (ENV_CONSOLE_LEVELS, "crit+loud", "unknown level \"loud\""),
+            (ENV_FILE_MAX_SIZE, "10 parsecs", "expected a size"),
+            (ENV_FILE_ROTATE, "weekly", "expected hourly, daily, midnight or never"),
+            (ENV_FILE_COMPRESS, "bzip2", "expected none, gzip or zstd"),
+            (ENV_FILE_KEEP_FILES, "-1", "expected a whole number"),
+            (ENV_FILE_KEEP_AGE, "7 fortnights", "expected a duration"),
+            (ENV_TIME_FORMAT, "%Q", "not a valid strftime format"),
+            (ENV_ON_IO_ERROR, "panic", "expected ignore, stderr or retry"),
+        ] {
+            set(&[(var, value)]);
+            let error = LogConfig::from_env().err().unwrap();
+            assert_eq!((error.key.as_str(), error.value.as_str()), (var, value));
+            assert!(error.to_string().starts_with(&format!("invalid {}={:?}: {}", var, value, hint)), "{}", error);
+        }
+        set(&[]);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_config_from_toml() {
+        let config = LogConfig::from_toml(r#"
+            level = "warn"
+            filter = "warn,myapp::db=error"
+            application_name = "svc"
+            time_format = "%H:%M"
+            on_io_error = "retry"
+
+            [console]
+            enabled = "off"
+            color = "never"
+
+            [file]
+            path = "logs/svc"
+            per_session = true
+            current_link = "logs/current.log"
+            format = "logfmt"
+            levels = "crit+success"
+            max_size = 1048576
+            rotate = "hourly"
+            keep_files = 5
+            keep_age = "12h"
+
+            [async]
+            enabled = true
+            overflow_policy = "shed_by_level"
+            max_latency_ms = 20
+        "#).unwrap();
+        assert_eq!(config.log_level, LogLevel::Warn);
+        assert_eq!(config.filter.as_deref(), Some("warn,myapp::db=error"));
+        assert_eq!(config.application_name, "svc");
+        assert!(!config.console_flag);
+        assert_eq!(config.color, ColorChoice::Never);
+        assert_eq!(config.log_filepath.as_deref(), Some("logs/svc"));
+        assert!(config.file_per_session);
+        assert_eq!(config.current_link.as_deref(), Some("logs/current.log"));
+        assert_eq!(config.file_format, OutputFormat::Logfmt);
+        assert_eq!(config.file_levels, LevelSet::only(&[LogLevel::Crit, LogLevel::Success]));
+        assert_eq!(config.console_levels, LevelSet::ALL);
+        assert_eq!(config.rotation, RotationPolicy::size(1024 * 1024).with_interval(RotationInterval::Hourly));
+        assert_eq!(config.retention, RetentionPolicy::keep_all().with_max_files(5).with_max_age(Duration::from_secs(12 * 60 * 60)));
+        assert!(config.async_flag);
+        assert_eq!(config.overflow_policy, OverflowPolicy::ShedByLevel);
+        assert_eq!(config.max_latency, Duration::from_millis(20));
+        assert!(matches!(config.io_error_policy, IoErrorPolicy::Retry { attempts: 3, .. }));
+
+        let error = |text: &str| LogConfig::from_toml(text).err().unwrap().to_string();
+        assert_eq!(error("level = \"loud\""), "invalid level=\"loud\": expected one of trace, debug, info, success, warn, error, crit");
+        assert_eq!(error("[file]\nformat = 3"), "invalid file.format=\"3\": expected a string, found integer");
+        assert_eq!(error("[file]\nmax_size = -1"), "invalid file.max_size=\"-1\": expected a size, found integer");
+        assert_eq!(error("[async]\nmax_latncy_ms = 3"), "invalid async.max_latncy_ms: unknown setting");
+        assert!(error("level = ").starts_with("invalid toml: "));
+
+        let missing = LogConfig::from_file(scratch_log_path("missing_config")).err().unwrap();
+        assert!(missing.to_string().contains("cannot read config file"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_config_hot_reload() {
+        use std::time::SystemTime;
+
+        let path = scratch_log_path("hot_reload").replace(".log", ".toml");
+        let write_config = |text: &str, age: u64| {
+            fs::write(&path, text).unwrap();
+            // Make every edit visible to the watcher, however coarse the filesystem's timestamps are
+            let mtime = SystemTime::now() + Duration::from_secs(age);
+            fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        };
+        let wait_for = |condition: &dyn Fn() -> bool| {
+            for _ in 0..200 {
+                if condition() {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            false
+        };
+
+        write_config("level = \"info\"", 0);
+        let records = Arc::new(Mutex::new(Vec::<LogRecord>::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        let watcher = logger.watch_config(&path, Duration::from_millis(5)).unwrap();
+
+        write_config("level = \"error\"\nfilter = \"app::db=warn\"", 1);
+        assert!(wait_for(&|| !logger.enabled(LogLevel::Info)));
+        assert!(logger.enabled_for(LogLevel::Warn, "app::db"));
+        assert!(!logger.enabled_for(LogLevel::Warn, "app::ui"));
+
+        // Rejected edit: the previous settings stay
+        write_config("level = \"loud\"", 2);
+        let has_message = |text: &str| records.lock().unwrap().iter().any(|r| r.message.contains(text));
+        assert!(wait_for(&|| has_message("Ignoring invalid log config")));
+        assert!(logger.enabled_for(LogLevel::Warn, "app::db"));
+        assert!(!logger.enabled(LogLevel::Info));
+
+        drop(watcher);
+        logger.shutdown().unwrap();
+        let records = records.lock().unwrap();
+        let applied = records.iter().find(|r| r.message.starts_with("Applied log config")).unwrap();
+        assert!(applied.message.ends_with("level ERROR, filter app::db=warn"));
+        assert_eq!(applied.level, LogLevel::Info);  // Logged even though Info is now filtered out
+    }
+
+    #[test]
+    fn test_set_level_at_runtime() {
+        for async_flag in [false, true] {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                async_flag,
+                log_level: LogLevel::Error,
+                filter: Some("myapp::db=crit".to_string()),
+                sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+                ..Default::default()
+            });
+            assert_eq!(logger.level(), Some(LogLevel::Error));
+
+            let log_from_threads = |message: &'static str| {
+                let handles: Vec<_> = (0..4).map(|_| {
+                    let logger = Arc::clone(&logger);
+                    thread::spawn(move || {
+                        logger.log_with_target(LogLevel::Info, "myapp::ui", message, CONSOLE_COLOR_INFO);
+                        logger.log_with_target(LogLevel::Error, "myapp::db", message, CONSOLE_COLOR_PINK);
+                    })
+                }).collect();
+                handles.into_iter().for_each(|h| h.join().unwrap());
+            };
+
+            log_from_threads("before");
+            logger.set_level(LogLevel::Info);
+            assert_eq!(logger.level(), Some(LogLevel::Info));
+            assert!(logger.enabled_for(LogLevel::Info, "myapp::ui"));
+            log_from_threads("after");
+            logger.set_level(LogLevel::Crit);
+            log_from_threads("raised");
+            logger.shutdown().unwrap();
+
+            // The per-target rule survives every level change: myapp::db stays at crit
+            let records = records.lock().unwrap();
+            assert_eq!(records.len(), 4);
+            assert!(records.iter().all(|r| r.message == "after" && r.target == "myapp::ui"));
+        }
+    }
+
+    #[test]
+    fn test_debug_and_trace_levels() {
+        let messages_at = |log_level: LogLevel| {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                log_level,
+                sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+                ..Default::default()
+            });
+            for level in [LogLevel::Trace, LogLevel::Debug, LogLevel::Info, LogLevel::Crit] {
+                logger.log(level, level.as_str(), level.color());
+            }
+            logger.shutdown().unwrap();
+            let records = records.lock().unwrap();
+            records.iter().map(|r| r.message.clone()).collect::<Vec<_>>()
+        };
+
+        // An Info threshold still means "Info and above"
+        assert_eq!(messages_at(LogLevel::Info), ["INFO", "CRIT"]);
+        assert_eq!(messages_at(LogLevel::Debug), ["DEBUG", "INFO", "CRIT"]);
+        assert_eq!(messages_at(LogLevel::Trace), ["TRACE", "DEBUG", "INFO", "CRIT"]);
+
+        let filter = TargetFilter::parse("info,myapp::db=trace", LogLevel::Info).unwrap();
+        assert!(filter.enabled_for(LogLevel::Trace, "myapp::db"));
+        assert!(!filter.enabled_for(LogLevel::Debug, "myapp::ui"));
+        assert_eq!(filter.max_level(), Some(LogLevel::Trace));
+        assert_eq!(LogLevel::from_name("DEBUG"), Some(LogLevel::Debug));
+        assert_eq!(format!("{:<7}|", LogLevel::Trace), "TRACE  |");
+    }
+
+    #[cfg(not(feature = "performance"))]
+    #[test]
+    fn test_debug_and_trace_macros() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        mlog::init(LogConfig {
+            console_flag: false,
+            log_level: LogLevel::Debug,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        trace!("hidden trace");
+        debug!(attempt = 2; "cache miss for {}", "key");
+        mlog::set_level(LogLevel::Trace);
+        trace!("visible trace");
+        shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let levels: Vec<_> = records.iter().map(|r| (r.level, r.message.as_str(), r.color)).collect();
+        assert_eq!(levels, [
+            (LogLevel::Debug, "cache miss for key", CONSOLE_COLOR_DEBUG),
+            (LogLevel::Trace, "visible trace", CONSOLE_COLOR_TRACE),
+        ]);
+    }
+
+    #[test]
+    fn test_level_sets() {
+        let ci = LevelSet::only(&[LogLevel::Crit, LogLevel::Success]);
+        assert!(ci.contains(LogLevel::Crit) && ci.contains(LogLevel::Success));
+        assert!(!ci.contains(LogLevel::Error) && !ci.contains(LogLevel::Info));
+        assert_eq!(LevelSet::parse("crit+success"), Ok(ci));
+        assert_eq!(LevelSet::parse("none + Success + crit"), Ok(ci));
+
+        let quiet = LevelSet::ALL.without(LogLevel::Success);
+        assert_eq!(LevelSet::parse("all-success"), Ok(quiet));
+        assert_eq!(LevelSet::parse("warn"), Ok(LevelSet::at_least(LogLevel::Warn)));
+        assert_eq!(LevelSet::parse("warn+debug").unwrap().levels().collect::<Vec<_>>(),
+            [LogLevel::Crit, LogLevel::Error, LogLevel::Warn, LogLevel::Debug]);
+        assert_eq!(LevelSet::parse("off"), Ok(LevelSet::NONE));
+        assert_eq!(LevelSet::parse("warn+loud"), Err("unknown level \"loud\"".to_string()));
+        assert!(LevelSet::parse("+crit").is_err());
+
+        // Display gives the shortest expression, and parses back to the same set
+        for (set, text) in [(ci, "crit+success"), (quiet, "trace-success"), (LevelSet::ALL, "trace"), (LevelSet::NONE, "none")] {
+            assert_eq!(set.to_string(), text);
+            assert_eq!(LevelSet::parse(text), Ok(set));
+        }
+
+        // Filter directives take sets too
+        let filter = TargetFilter::parse("crit+success,myapp::db=all-success", LogLevel::Info).unwrap();
+        assert!(filter.enabled_for(LogLevel::Success, "myapp::ui"));
+        assert!(!filter.enabled_for(LogLevel::Error, "myapp::ui"));
+        assert!(filter.enabled_for(LogLevel::Trace, "myapp::db"));
+        assert!(!filter.enabled_for(LogLevel::Success, "myapp::db"));
+        assert_eq!(filter.default_levels(), ci);
+        assert_eq!(filter.default_level(), None);  // not a threshold
+        assert_eq!(ci.threshold(), None);
+        assert_eq!(LevelSet::at_least(LogLevel::Warn).threshold(), Some(LogLevel::Warn));
+        assert_eq!(LevelSet::parse("warn+error").unwrap().threshold(), Some(LogLevel::Warn));
+        assert_eq!(LevelSet::NONE.threshold(), None);
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        logger.set_levels(ci);
+        assert_eq!(logger.levels(), ci);
+        assert_eq!(logger.level(), None);
+        for level in [LogLevel::Info, LogLevel::Success, LogLevel::Warn, LogLevel::Error, LogLevel::Crit] {
+            logger.log(level, level.as_str(), level.color());
+        }
+        logger.shutdown().unwrap();
+        let messages: Vec<_> = records.lock().unwrap().iter().map(|r| r.message.clone()).collect();
+        assert_eq!(messages, ["SUCCESS", "CRIT"]);
+    }
+
+    #[test]
+    fn test_sinks_with_level_sets() {
+        let dashboard = SharedBuffer::default();
+        let path = scratch_log_path("level_sets");
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_level: LogLevel::Debug,
+            log_filepath: Some(path.clone()),
+            file_levels: LevelSet::parse("all-success").unwrap(),
+            file_pattern: Some("{level} {msg}".to_string()),
+            sinks: vec![
+                SinkConfig::new(WriterSink::new(dashboard.clone()))
+                    .with_levels(LevelSet::only(&[LogLevel::Crit, LogLevel::Success]))
+                    .with_formatter(|record: &LogRecord| format!("{} {}", record.level, record.message)),
+            ],
+            ..Default::default()
+        });
+
+        for level in [LogLevel::Trace, LogLevel::Debug, LogLevel::Info, LogLevel::Success, LogLevel::Warn, LogLevel::Error, LogLevel::Crit] {
+            logger.log(level, "level set", level.color());
+        }
+        logger.shutdown().unwrap();
+
+        assert_eq!(dashboard.contents(), "SUCCESS level set\nCRIT level set\n");
+        // The file gets everything the logger's Debug level lets through, except Success
+        assert_eq!(read_log_lines(&path, "level set"),
+            ["DEBUG level set", "INFO level set", "WARN level set", "ERROR level set", "CRIT level set"]);
+    }
+
+    // Files `FileSink::rotate` renamed `path` to, oldest first
+    fn rotated_files(path: &str) -> Vec<String> {
+        let prefix = format!("{}.", path);
+        let mut files: Vec<String> = fs::read_dir(env!("CARGO_TARGET_TMPDIR"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+            .filter(|file| file.starts_with(&prefix))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_size_rotation_in_every_mode() {
+        for (mode, async_flag, multi_threaded_flag) in [("sync", false, false), ("mt", false, true), ("async", true, false)] {
+            let path = scratch_log_path(&format!("size_rotation_{}", mode));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                async_flag,
+                multi_threaded_flag,
+                log_filepath: Some(path.clone()),
+                file_pattern: Some("{msg}".to_string()),
+                rotation: RotationPolicy::size(1000),
+                ..Default::default()
+            });
+            for i in 0..100 {
+                logger.log(LogLevel::Info, &format!("rotation line {:03}", i), CONSOLE_COLOR_INFO);
+            }
+            logger.shutdown().unwrap();
+
+            // Files are rotated on the write path, before they pass the limit
+            let mut files = rotated_files(&path);
+            assert!(files.len() >= 2, "{}: {:?}", mode, files);
+            files.push(path.clone());
+            let mut lines = Vec::new();
+            for file in &files {
+                assert!(fs::metadata(file).unwrap().len() <= 1000, "{}: {} is too large", mode, file);
+                lines.extend(read_log_lines(file, "rotation line"));
+            }
+            let expected: Vec<_> = (0..100).map(|i| format!("rotation line {:03}", i)).collect();
+            assert_eq!(lines, expected, "{}", mode);
+            assert_eq!(read_log_lines(&files[1], "--- Log rotated at").len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_time_rotation() {
+        let path = scratch_log_path("time_rotation");
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_filepath: Some(path.clone()),
+            file_format: OutputFormat::Logfmt,
+            rotation: RotationPolicy::time(RotationInterval::Daily).with_max_size(DEFAULT_MAX_FILE_SIZE),
+            ..Default::default()
+        });
+        logger.log(LogLevel::Info, "today", CONSOLE_COLOR_INFO);
+        assert!(rotated_files(&path).is_empty());
+
+        // A record from a day later starts a new file
+        let mut record = LogRecord::new(LogLevel::Info, "app", "tomorrow".to_string(), CONSOLE_COLOR_INFO);
+        record.time = Local::now() + chrono::Duration::hours(25);
+        logger.log_record(record);
+        logger.shutdown().unwrap();
+
+        let rotated = rotated_files(&path);
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(read_log_lines(&rotated[0], "msg=").len(), 2);  // session start, "today"
+        assert_eq!(read_log_lines(&rotated[0], "today").len(), 1);
+        let current = read_log_lines(&path, "msg=");
+        assert!(current[0].contains("event=log_rotated"), "{:?}", current);
+        assert!(current[1].contains("tomorrow"));
+        assert!(current[2].contains("event=session_end"));
+    }
+
+    #[test]
+    fn test_time_rotation_after_restart() {
+        let hours = |n: u64| Duration::from_secs(n * 3600);
+        for (interval, age, rotated) in [
+            (RotationInterval::Hourly, hours(48), true),
+            (RotationInterval::Midnight, hours(48), true),
+            (RotationInterval::Daily, hours(25), true),
+            (RotationInterval::Daily, hours(1), false),  // restarted within the period: same file
+        ] {
+            let path = scratch_log_path(&format!("restart_{:?}_{}", interval, age.as_secs()));
+            fs::write(&path, "previous run\n").unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(std::time::SystemTime::now() - age).unwrap();
+            drop(file);
+
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                log_filepath: Some(path.clone()),
+                file_pattern: Some("{msg}".to_string()),
+                rotation: RotationPolicy::time(interval),
+                ..Default::default()
+            });
+            logger.log(LogLevel::Info, "this run", CONSOLE_COLOR_INFO);
+            logger.shutdown().unwrap();
+
+            let files = rotated_files(&path);
+            let current = fs::read_to_string(&path).unwrap();
+            assert!(current.contains("this run"), "{:?}", interval);
+            if rotated {
+                assert_eq!(files.len(), 1, "{:?}", interval);
+                assert_eq!(fs::read_to_string(&files[0]).unwrap(), "previous run\n");
+                assert!(!current.contains("previous run"), "{:?}", interval);
+            } else {
+                assert!(files.is_empty(), "{:?}: {:?}", interval, files);
+                assert!(current.starts_with("previous run\n"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_new_errors() {
+        use std::error::Error;
+
+        // Everything is checked before any output is started
+        let try_new = |config: LogConfig| Logger::try_new(config).err().unwrap();
+
+        // A path below a regular file can never be opened, not even by root
+        let blocker = scratch_log_path("not_a_dir");
+        fs::write(&blocker, "").unwrap();
+        let error = try_new(LogConfig { log_filepath: Some(format!("{}/app.log", blocker)), ..Default::default() });
+        assert!(matches!(error, MlogError::Io { .. }), "{:?}", error);
+        assert!(error.to_string().starts_with(&format!("cannot open log file {}/app.log: ", blocker)), "{}", error);
+        assert!(error.source().is_some());
+
+        for (config, key) in [
+            (LogConfig { console_pattern: Some("{lvl}".to_string()), ..Default::default() }, "console_pattern"),
+            (LogConfig { filter: Some("info,db=loud".to_string()), ..Default::default() }, "filter"),
+            (LogConfig { time_format: "%Q".to_string(), ..Default::default() }, "time_format"),
+            (LogConfig { log_filepath: Some("logs/{host}.log".to_string()), ..Default::default() }, "log_filepath"),
+            (LogConfig { async_flag: true, buffer_capacity: 0, ..Default::default() }, "buffer_capacity"),
+            (LogConfig { async_flag: true, buffer_capacity: MAX_BUFFER_CAPACITY + 1, ..Default::default() }, "buffer_capacity"),
+        ] {
+            match try_new(config) {
+                MlogError::InvalidConfig(e) => assert_eq!(e.key, key),
+                other => panic!("{}: {:?}", key, other),
+            }
+        }
+        let error = try_new(LogConfig { buffer_capacity: 0, ..Default::default() });
+        assert_eq!(error.to_string(), format!("invalid buffer_capacity=\"0\": expected 1 to {}", MAX_BUFFER_CAPACITY));
+        Logger::new(LogConfig { console_flag: false, async_flag: true, buffer_capacity: 1, ..Default::default() }).shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_try_init_already_initialized() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        try_init(LogConfig { console_flag: false, ..Default::default() }).unwrap();
+
+        let path = scratch_log_path("second_init");
+        let error = try_init(LogConfig { console_flag: false, log_filepath: Some(path.clone()), ..Default::default() });
+        assert!(matches!(error, Err(MlogError::AlreadyInitialized)));
+        assert_eq!(error.err().unwrap().to_string(), "logger is already initialized");
+        assert!(!std::path::Path::new(&path).exists());  // the second config was never used
+        shutdown().unwrap();
+
+        // Failing to start leaves no global logger behind
+        let error = try_init(LogConfig { console_flag: false, filter: Some("=warn".to_string()), ..Default::default() });
+        assert!(matches!(error, Err(MlogError::InvalidConfig(_))));
+        assert!(with_logger_opt(|_| ()).is_none());
+    }
+
+    #[test]
+    fn test_logging_continues_after_sink_panic() {
+        for multi_threaded_flag in [false, true] {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                multi_threaded_flag,
+                sinks: vec![
+                    SinkConfig::new(PanickingSink("boom")),
+                    SinkConfig::new(CollectSink(Arc::clone(&records))),
+                ],
+                ..Default::default()
+            });
+
+            let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                logger.log(LogLevel::Info, "boom", CONSOLE_COLOR_INFO);
+            }));
+            assert!(panicked.is_err());
+
+            // The locks poisoned by the panic do not take logging down with them
+            logger.log(LogLevel::Info, "after", CONSOLE_COLOR_INFO);
+            logger.flush();
+            logger.shutdown().unwrap();
+
+            let messages: Vec<_> = records.lock().unwrap().iter().map(|r| r.message.clone()).collect();
+            assert_eq!(messages, ["after"]);
+        }
+    }
+
+    #[test]
+    fn test_sink_panic_on_async_writer() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        mlog::init(LogConfig {
+            console_flag: false,
+            async_flag: true,
+            sinks: vec![
+                SinkConfig::new(PanickingSink("boom")),
+                SinkConfig::new(CollectSink(Arc::clone(&records))),
+            ],
+            ..Default::default()
+        });
+
+        // The writer panics while it holds the queue and the sink; the panic hook's Crit record and
+        // shutdown must not wait for those locks
+        with_logger(|logger| logger.log(LogLevel::Info, "boom", CONSOLE_COLOR_INFO));
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        let crit = loop {
+            let crit = records.lock().unwrap().iter().find(|record| record.level == LogLevel::Crit).cloned();
+            if let Some(crit) = crit {
+                break crit;
+            }
+            assert!(std::time::Instant::now() < deadline, "panic record not written");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(crit.message.contains("sink cannot write \"boom\""), "{}", crit.message);
+        assert!(with_logger_opt(|_| ()).is_none(), "the panic hook shuts the logger down");
+        shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_path_templates() {
+        // Only this test touches these variables
+        std::env::set_var("MLOG_TEST_LOG_ROOT", "/var/log");
+        std::env::remove_var("MLOG_TEST_UNSET");
+        let today = Local::now();
+        let pid = std::process::id();
+
+        assert_eq!(expand_path("$MLOG_TEST_LOG_ROOT/{app}/{date}-{pid}.log", "svc/api").unwrap(),
+            format!("/var/log/svc_api/{}-{}.log", today.format("%Y-%m-%d"), pid));
+        assert_eq!(expand_path("${MLOG_TEST_LOG_ROOT}x/{date:%Y%m}/{{a}}$.log", "svc").unwrap(),
+            format!("/var/logx/{}/{{a}}$.log", today.format("%Y%m")));
+        if let Ok(home) = std::env::var("HOME") {
+            assert_eq!(expand_path("~/logs/app.log", "svc").unwrap(), format!("{}/logs/app.log", home));
+        }
+        assert_eq!(expand_path("logs/~app.log", "svc").unwrap(), "logs/~app.log");
+        assert_eq!(expand_path("logs/price$5-$-$.log", "svc").unwrap(), "logs/price$5-$-$.log");
+
+        let error = |template: &str| expand_path(template, "svc").err().unwrap().to_string();
+        assert_eq!(error("logs/{host}.log"), "invalid log_filepath=\"logs/{host}.log\": unknown placeholder {host}");
+        assert!(error("$MLOG_TEST_UNSET/app.log").ends_with("environment variable MLOG_TEST_UNSET is not set"));
+        assert!(error("logs/{date.log").ends_with("unclosed '{'"));
+        assert!(error("logs/{date:%Q}.log").ends_with("invalid date format \"%Q\""));
+    }
+
+    #[test]
+    fn test_io_error_policies() {
+        let run = |policy: IoErrorPolicy| {
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                io_error_policy: policy,
+                sinks: vec![SinkConfig::new(FlakySink { failures: 2, written: Arc::clone(&written) })],
+                ..Default::default()
+            });
+            for message in ["first", "second", "third"] {
+                logger.log(LogLevel::Info, message, CONSOLE_COLOR_INFO);
+            }
+            logger.shutdown().unwrap();
+            let written = written.lock().unwrap().clone();
+            (written, logger.io_error_count())
+        };
+
+        // No panics, and every failure is counted
+        assert_eq!(run(IoErrorPolicy::Ignore), (vec!["third".to_string()], 2));
+        assert_eq!(run(IoErrorPolicy::Stderr), (vec!["third".to_string()], 2));
+
+        let retry = IoErrorPolicy::Retry { attempts: 3, backoff: Duration::from_millis(1) };
+        assert_eq!(run(retry), (vec!["first".to_string(), "second".to_string(), "third".to_string()], 2));
+        let short_retry = IoErrorPolicy::Retry { attempts: 1, backoff: Duration::from_millis(1) };
+        assert_eq!(run(short_retry), (vec!["second".to_string(), "third".to_string()], 2));
+
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&failures);
+        let callback = IoErrorPolicy::callback(move |failure: &SinkError| {
+            seen.lock().unwrap().push((failure.operation, failure.error.kind(), failure.line.unwrap().to_string()));
+        });
+        assert_eq!(run(callback), (vec!["third".to_string()], 2));
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!((failures[0].0, failures[0].1), (SinkOperation::Write, io::ErrorKind::StorageFull));
+        assert!(failures[0].2.ends_with("first") && failures[1].2.ends_with("second"));
+    }
+
+    #[test]
+    fn test_retry_gives_up_on_a_sink_that_stays_down() {
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            io_error_policy: IoErrorPolicy::Retry { attempts: 3, backoff: Duration::from_millis(20) },
+            sinks: vec![SinkConfig::new(FlakySink { failures: usize::MAX, written: Arc::default() })],
+            ..Default::default()
+        });
+
+        // Only the first record waits for the retries (20 + 40 + 80 ms), the rest are dropped at once
+        let start = std::time::Instant::now();
+        for n in 0..20 {
+            logger.log(LogLevel::Info, &format!("record {}", n), CONSOLE_COLOR_INFO);
+        }
+        let elapsed = start.elapsed();
+        logger.shutdown().unwrap();
+        assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+        assert_eq!(logger.io_error_count(), 20 + 3);
+    }
+
+    #[test]
+    fn test_io_error_callback_can_log() {
+        for async_flag in [false, true] {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let target: Arc<std::sync::OnceLock<std::sync::Weak<Logger>>> = Arc::default();
+            let callback_target = Arc::clone(&target);
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                async_flag,
+                multi_threaded_flag: true,
+                io_error_policy: IoErrorPolicy::callback(move |failure: &SinkError| {
+                    // Runs with the failing sink locked; the record goes to the other sinks
+                    if let Some(logger) = callback_target.get().and_then(std::sync::Weak::upgrade) {
+                        logger.log(LogLevel::Error, &format!("sink failed: {}", failure.error), CONSOLE_COLOR_PINK);
+                    }
+                }),
+                sinks: vec![
+                    SinkConfig::new(FlakySink { failures: 1, written: Arc::default() }),
+                    SinkConfig::new(CollectSink(Arc::clone(&records))),
+                ],
+                ..Default::default()
+            });
+            target.set(Arc::downgrade(&logger)).unwrap();
+
+            logger.log(LogLevel::Info, "first", CONSOLE_COLOR_INFO);
+            logger.log(LogLevel::Info, "second", CONSOLE_COLOR_INFO);
+            logger.shutdown().unwrap();
+
+            let messages: Vec<_> = records.lock().unwrap().iter().map(|r| r.message.clone()).collect();
+            assert_eq!(messages, ["sink failed: disk full", "first", "second"]);
+        }
+    }
+
+    #[test]
+    fn test_file_sink_recovers_after_directory_removed() {
+        let root = scratch_log_path("recover").replace(".log", "");
+        let path = format!("{}/logs/app.log", root);
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_filepath: Some(path.clone()),
+            ..Default::default()
+        });
+        logger.log(LogLevel::Info, "before removal", CONSOLE_COLOR_INFO);
+        fs::remove_dir_all(&root).unwrap();
+
+        // The sink notices on the first write after its periodic check and starts the file again
+        thread::sleep(Duration::from_millis(1100));
+        logger.log(LogLevel::Info, "after removal", CONSOLE_COLOR_INFO);
+        logger.shutdown().unwrap();
+
+        assert_eq!(read_log_lines(&path, "after removal").len(), 1);
+        assert!(read_log_lines(&path, "before removal").is_empty());
+        assert_eq!(logger.io_error_count(), 0);
+    }
+
+    #[test]
+    fn test_file_template_and_fresh_file_per_session() {
+        let root = scratch_log_path("templates").replace(".log", "");
+        let session = |message: &str| {
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                application_name: "svc".to_string(),
+                log_filepath: Some(format!("{}/{{app}}/{{date:%Y}}/app.log", root)),  // directories do not exist yet
+                file_per_session: true,
+                ..Default::default()
+            });
+            logger.log(LogLevel::Info, message, CONSOLE_COLOR_INFO);
+            logger.shutdown().unwrap();
+        };
+        session("first session");
+        session("second session");
+
+        let path = format!("{}/svc/{}/app.log", root, Local::now().format("%Y"));
+        assert_eq!(read_log_lines(&path, "session").len(), 1);
+        assert_eq!(read_log_lines(&path, "second session").len(), 1);
+        let dir = std::path::Path::new(&path).parent().unwrap();
+        let previous: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).filter(|file| *file != std::path::Path::new(&path)).collect();
+        assert_eq!(previous.len(), 1, "{:?}", previous);
+        assert!(fs::read_to_string(&previous[0]).unwrap().contains("first session"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_current_link() {
+        let root = scratch_log_path("current_link").replace(".log", "");
+        let link = format!("{}/current.log", root);
+        for pid in ["100", "200"] {
+            std::env::set_var("MLOG_TEST_FAKE_PID", pid);  // stands in for {pid}, which is the same for both
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                log_filepath: Some(format!("{}/app-$MLOG_TEST_FAKE_PID.log", root)),
+                current_link: Some(link.clone()),
+                ..Default::default()
+            });
+            logger.log(LogLevel::Info, &format!("written by {}", pid), CONSOLE_COLOR_INFO);
+            logger.shutdown().unwrap();
+
+            assert_eq!(fs::read_link(&link).unwrap(), std::path::Path::new(&format!("app-{}.log", pid)));
+            let lines = read_log_lines(&link, "written by");
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].ends_with(&format!("written by {}", pid)));
+        }
+
+        // A link in another directory gets an absolute target
+        let elsewhere = format!("{}/links/current.log", root);
+        let sink = FileSink::new(&format!("{}/app-100.log", root)).unwrap().with_current_link(&elsewhere).unwrap();
+        assert!(fs::read_link(&elsewhere).unwrap().is_absolute());
+        assert_eq!(fs::read_to_string(&elsewhere).unwrap(), fs::read_to_string(sink.path()).unwrap());
+
+        // A regular file is never replaced
+        let regular = format!("{}/regular.log", root);
+        fs::write(&regular, "mine").unwrap();
+        assert!(FileSink::new(&format!("{}/app-100.log", root)).unwrap().with_current_link(&regular).is_err());
+        assert_eq!(fs::read_to_string(&regular).unwrap(), "mine");
+    }
+
+    #[test]
+    fn test_retention_after_rotation() {
+        let path = scratch_log_path("retention_count");
+        // Not the logger's own rotated files, so never touched
+        let decoys = [format!("{}.backup", path), format!("{}.20240101000000.000.txt", path)];
+        for decoy in &decoys {
+            fs::write(decoy, "keep me").unwrap();
+        }
+
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_filepath: Some(path.clone()),
+            file_pattern: Some("{msg}".to_string()),
+            rotation: RotationPolicy::size(500),
+            retention: RetentionPolicy::keep_all().with_max_files(2),
+            ..Default::default()
+        });
+        for i in 0..100 {
+            logger.log(LogLevel::Info, &format!("retained line {:03}", i), CONSOLE_COLOR_INFO);
+        }
+        logger.shutdown().unwrap();
+
+        let rotated: Vec<_> = rotated_files(&path).into_iter().filter(|file| !decoys.contains(file)).collect();
+        assert_eq!(rotated.len(), 2, "{:?}", rotated);
+        // The kept files are the newest ones; removals are noted in the log (notes in files that
+        // were removed later are gone with them)
+        let files: Vec<_> = rotated.iter().chain([&path]).collect();
+        let lines: Vec<_> = files.iter().flat_map(|file| read_log_lines(file, "retained line")).collect();
+        let first: usize = lines[0]["retained line ".len()..].parse().unwrap();
+        assert!(first > 0);
+        let expected: Vec<_> = (first..100).map(|i| format!("retained line {:03}", i)).collect();
+        assert_eq!(lines, expected);
+        let notes: usize = files.iter().map(|file| read_log_lines(file, "--- Removed old log file").len()).sum();
+        assert!(notes >= 1);
+        for decoy in &decoys {
+            assert_eq!(fs::read_to_string(decoy).unwrap(), "keep me");
+        }
+    }
+
+    #[test]
+    fn test_retention_at_startup() {
+        use std::time::SystemTime;
+
+        let path = scratch_log_path("retention_startup");
+        let old_file = |stamp: &str, bytes: usize, days: u64| {
+            let file = format!("{}.{}", path, stamp);
+            fs::write(&file, "x".repeat(bytes)).unwrap();
+            let mtime = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+            fs::File::options().write(true).open(&file).unwrap().set_modified(mtime).unwrap();
+            file
+        };
+        let ancient = old_file("20200101000000.000.gz", 10, 30);
+        let large = old_file("20240101000000.000", 5000, 1);
+        let recent = old_file("20240102000000.000.1", 100, 1);
+        let newest = old_file("20240103000000.000", 100, 0);
+
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_filepath: Some(path.clone()),
+            file_format: OutputFormat::Json,
+            retention: RetentionPolicy::keep_all()
+                .with_max_age(Duration::from_secs(7 * 24 * 60 * 60))
+                .with_max_total_size(1000),
+            ..Default::default()
+        });
+        logger.shutdown().unwrap();
+
+        let exists = |file: &str| std::path::Path::new(file).exists();
+        assert!(!exists(&ancient) && !exists(&large));
+        assert!(exists(&recent) && exists(&newest));
+
+        let removed = read_log_lines(&path, "\"event\":\"log_removed\"");
+        assert_eq!(removed.len(), 2);
+        assert!(removed[0].contains(&format!("\"file\":\"{}\",\"bytes\":5000", large)), "{}", removed[0]);
+        assert!(removed[1].contains("Removed old log file"));
+    }
+
+    #[test]
+    fn test_retention_skips_files_being_compressed() {
+        use std::time::SystemTime;
+
+        let path = scratch_log_path("retention_compressing");
+        let file = |name: &str, hours: u64| {
+            let file = format!("{}.{}", path, name);
+            fs::write(&file, "x").unwrap();
+            let mtime = SystemTime::now() - Duration::from_secs(hours * 60 * 60);
+            fs::File::options().write(true).open(&file).unwrap().set_modified(mtime).unwrap();
+            file
+        };
+        let compressing = file("20240104000000.000", 1);
+        let partial = file("20240104000000.000.gz.tmp", 0);
+        let compressed_original = file("20240103000000.000", 1);
+        let compressed = file("20240103000000.000.zst", 0);
+        let crashed = file("20240102000000.000", 1);
+        let stale_partial = file("20240102000000.000.gz.tmp", 2);
+        let oldest = file("20240101000000.000", 1);
+
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_filepath: Some(path.clone()),
+            retention: RetentionPolicy::keep_all().with_max_files(1),
+            ..Default::default()
+        });
+        logger.shutdown().unwrap();
+
+        // Only the compressed copy counts towards max_files; a stale partial file does not protect its original
+        let exists = |file: &str| std::path::Path::new(file).exists();
+        for kept in [&compressing, &partial, &compressed_original, &compressed, &stale_partial] {
+            assert!(exists(kept), "{} was removed", kept);
+        }
+        assert!(!exists(&crashed) && !exists(&oldest));
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn test_rotated_files_are_compressed() {
+        use std::io::Read;
+
+        let mut compressions = Vec::new();
+        #[cfg(feature = "gzip")]
+        compressions.push((Compression::Gzip, ".gz"));
+        #[cfg(feature = "zstd")]
+        compressions.push((Compression::Zstd, ".zst"));
+
+        for (compression, extension) in compressions {
+            let path = scratch_log_path(&format!("compressed{}", extension.replace('.', "_")));
+            let logger = Logger::new(LogConfig {
+                console_flag: false,
+                log_filepath: Some(path.clone()),
+                file_pattern: Some("{msg}".to_string()),
+                rotation: RotationPolicy::size(1000),
+                compression,
+                ..Default::default()
+            });
+            for i in 0..100 {
+                logger.log(LogLevel::Info, &format!("compressed line {:03}", i), CONSOLE_COLOR_INFO);
+            }
+            logger.shutdown().unwrap();
+            drop(logger);  // Waits for the compression thread to finish
+
+            let rotated = rotated_files(&path);
+            assert!(rotated.len() >= 2, "{:?}", rotated);
+            let mut text = String::new();
+            for file in &rotated {
+                assert!(file.ends_with(extension), "{} was not compressed", file);
+                let data = fs::File::open(file).unwrap();
+                match extension {
+                    #[cfg(feature = "gzip")]
+                    ".gz" => flate2::read::GzDecoder::new(data).read_to_string(&mut text).unwrap(),
+                    #[cfg(feature = "zstd")]
+                    ".zst" => zstd::stream::read::Decoder::new(data).unwrap().read_to_string(&mut text).unwrap(),
+                    _ => unreachable!(),
+                };
+            }
+            text.push_str(&fs::read_to_string(&path).unwrap());
+            let lines: Vec<_> = text.lines().filter(|line| line.contains("compressed line")).collect();
+            assert_eq!(lines.len(), 100);
+            assert_eq!(lines[99], "compressed line 099");
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_failed_compression_keeps_file() {
+        let path = scratch_log_path("compress_failure");
+        fs::write(&path, "rotated contents\n").unwrap();
+        // The temporary output cannot be created where a directory is in the way
+        fs::create_dir_all(format!("{}.gz.tmp", path)).unwrap();
+
+        assert!(Compression::Gzip.compress_file(path.as_ref()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "rotated contents\n");
+        assert!(!std::path::Path::new(&format!("{}.gz", path)).exists());
+    }
+
+    #[test]
+    fn test_json_lines_file() {
+        let path = scratch_log_path("json_lines");
+        let logger = Logger::new(LogConfig {
+            application_name: "json \"app\"".to_string(),
+            log_filepath: Some(path.clone()),
+            file_format: OutputFormat::Json,
+            console_flag: false,
+            ..Default::default()
+        });
+        logger.log_with_target(LogLevel::Warn, "app::db", "quote \" backslash \\ newline \n tab \t bell \x07", CONSOLE_COLOR_WARN);
+        let mut record = LogRecord::new(LogLevel::Error, "app::net", "\x1b[31mred\x1b[0m".to_string(), CONSOLE_COLOR_PINK);
+        record.file = Some("src/net.rs".to_string());
+        record.line = Some(12);
+        record.fields = vec![
+            ("status".to_string(), Value::U64(503)),
+            ("ratio".to_string(), Value::F64(f64::NAN)),
+            ("peer".to_string(), Value::Str("a\"b".to_string())),
+        ];
+        logger.log_record(record);
+        logger.shutdown().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.starts_with("{\"timestamp\":\"") && line.ends_with('}')));
+
+        assert!(lines[0].contains(r#""level":"INFO","app":"json \"app\"","message":"Session started""#));
+        assert!(lines[0].ends_with(r#""fields":{"event":"session_start"}}"#));
+        assert!(lines[1].contains(r#""level":"WARN","app":"json \"app\"","message":"quote \" backslash \\ newline \n tab \t bell \u0007","thread":"#));
+        assert!(lines[1].contains(r#""target":"app::db"}"#));
+        assert!(lines[2].contains(r#""message":"red""#));
+        assert!(lines[2].ends_with(r#""target":"app::net","file":"src/net.rs","line":12,"fields":{"status":503,"ratio":null,"peer":"a\"b"}}"#));
+        assert!(lines[3].ends_with(r#""fields":{"event":"session_end"}}"#));
+
+        // RFC 3339 timestamp with the local offset
+        let timestamp = &lines[1]["{\"timestamp\":\"".len()..lines[1].find("\",\"level\"").unwrap()];
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    }
+
+    #[test]
+    fn test_logfmt_file() {
+        let path = scratch_log_path("logfmt");
+        let logger = Logger::new(LogConfig {
+            application_name: "my app".to_string(),
+            log_filepath: Some(path.clone()),
+            file_format: OutputFormat::Logfmt,
+            console_flag: false,
+            ..Default::default()
+        });
+        let mut record = LogRecord::new(LogLevel::Warn, "app::db", "said \"hi\"\nthen left \\o/".to_string(), CONSOLE_COLOR_WARN);
+        record.line = Some(7);
+        record.fields = vec![
+            ("user".to_string(), Value::Str("ada".to_string())),
+            ("query".to_string(), Value::Str("a = b".to_string())),
+            ("empty".to_string(), Value::Str(String::new())),
+            ("bad key".to_string(), Value::F64(0.5)),
+        ];
+        logger.log_record(record);
+        logger.shutdown().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.starts_with("ts=")));
+
+        assert!(lines[0].contains(r#" level=INFO app="my app" msg="Session started" thread="#));
+        assert!(lines[0].ends_with(" target=mlog event=session_start"));
+        assert!(lines[1].contains(r#" level=WARN app="my app" msg="said \"hi\"\nthen left \\o/" thread="#));
+        assert!(lines[1].ends_with(r#" target=app::db line=7 user=ada query="a = b" empty="" bad_key=0.5"#));
+        assert!(lines[2].ends_with(" event=session_end"));
+
+        let ts = &lines[1]["ts=".len()..lines[1].find(' ').unwrap()];
+        assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok());
+    }
+
+    #[test]
+    fn test_pattern_padding_and_alignment() {
+        let record = LogRecord::new(LogLevel::Warn, "myapp::net", "hello world".to_string(), CONSOLE_COLOR_WARN);
+        let format = |pattern: &str| PatternFormatter::new(pattern).unwrap().format(&record);
+
+        assert_eq!(format("{level:>5}|{level:<6}|{level:*^8}|"), " WARN|WARN  |**WARN**|");
+        assert_eq!(format("{target}: {msg:.5}"), "myapp::net: hello");
+        assert_eq!(format("{{{msg:>13}}}"), "{  hello world}");
+        assert_eq!(format("{message:-<13}"), "hello world--");
+
+        let time = PatternFormatter::new("{time}").unwrap().with_time_format("%Y").format(&record);
+        assert_eq!(time, record.time.format("%Y").to_string());
+        assert_eq!(format!("{:>9}", LogLevel::Success), "  SUCCESS");
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        assert!(PatternFormatter::new("{levle}").is_err());
+        assert!(PatternFormatter::new("{msg").is_err());
+        assert!(PatternFormatter::new("msg}").is_err());
+        assert!(PatternFormatter::new("{msg:>x}").is_err());
+        let err = PatternFormatter::new("{nope}").unwrap_err();
+        assert!(err.to_string().contains("unknown placeholder {nope}"));
+    }
+
+    #[test]
+    fn test_file_pattern_with_thread_and_target() {
+        let path = scratch_log_path("file_pattern");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            file_pattern: Some("{level:>5} [{thread}] {target}: {msg}".to_string()),
+            ..Default::default()
+        });
+
+        let worker = Arc::clone(&logger);
+        thread::Builder::new()
+            .name("worker-1".to_string())
+            .spawn(move || worker.log_with_target(LogLevel::Error, "myapp::db", "query failed", CONSOLE_COLOR_PINK))
+            .unwrap()
+            .join()
+            .unwrap();
+        logger.log_with_target(LogLevel::Info, "myapp", "started", CONSOLE_COLOR_INFO);
+        logger.shutdown().unwrap();
+
+        assert_eq!(read_log_lines(&path, "query failed"), ["ERROR [worker-1] myapp::db: query failed"]);
+        let started = read_log_lines(&path, "started");
+        assert!(started[0].starts_with(" INFO [") && started[0].ends_with("] myapp: started"));
+    }
+
+    #[test]
+    fn test_color_depth_fallback() {
+        assert_eq!(ColorDepth::TrueColor.adapt(CONSOLE_COLOR_INFO), CONSOLE_COLOR_INFO);
+        assert_eq!(ColorDepth::Ansi256.adapt(CONSOLE_COLOR_INFO), "\x1b[38;5;111m");
+        assert_eq!(ColorDepth::Ansi256.adapt(CONSOLE_COLOR_PINK), CONSOLE_COLOR_PINK);
+
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_COLOR_INFO), "\x1b[94m");
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_COLOR_WARN), "\x1b[93m");
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_COLOR_PINK), "\x1b[95m");
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_BG_COLOR_SUCCESS), "\x1b[30;102m");
+
+        // Sequences that already fit in 16 colors are left alone
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_COLOR_RED), CONSOLE_COLOR_RED);
+        assert_eq!(ColorDepth::Ansi16.adapt(CONSOLE_BG_COLOR_RED), CONSOLE_BG_COLOR_RED);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m plain"), "red plain");
+        assert_eq!(strip_ansi("\x1b[38;2;1;2;3mrgb\x1b[2K\x1b7"), "rgb");
+        assert_eq!(strip_ansi("no escapes"), "no escapes");
+        assert!(ColorChoice::Always.enabled_for_stdout());
+        assert!(!ColorChoice::Never.enabled_for_stdout());
+    }
+
+    #[test]
+    fn test_auto_color_env_precedence() {
+        use std::io::IsTerminal;
+
+        let _vars = COLOR_VARS.lock().unwrap_or_else(|e| e.into_inner());
+        let names = ["CLICOLOR_FORCE", "NO_COLOR", "TERM"];
+        let saved: Vec<_> = names.iter().map(std::env::var_os).collect();
+        let terminal = io::stdout().is_terminal();
+
+        // (CLICOLOR_FORCE, NO_COLOR, TERM) -> colored; CLICOLOR_FORCE wins, then NO_COLOR or TERM=dumb
+        for (force, no_color, term, expected) in [
+            (None, None, None, terminal),
+            (None, None, Some("xterm-256color"), terminal),
+            (None, None, Some("dumb"), false),
+            (None, Some("1"), None, false),
+            (None, Some("1"), Some("dumb"), false),
+            (None, Some(""), None, terminal),  // empty NO_COLOR is ignored
+            (Some("1"), None, None, true),
+            (Some("1"), None, Some("dumb"), true),
+            (Some("1"), Some("1"), None, true),
+            (Some("1"), Some("1"), Some("dumb"), true),
+            (Some("0"), None, None, terminal),  // CLICOLOR_FORCE=0 is not forcing
+            (Some("0"), Some("1"), None, false),
+            (Some("0"), None, Some("dumb"), false),
+        ] {
+            for (name, value) in names.iter().zip([force, no_color, term]) {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+            assert_eq!(ColorChoice::Auto.enabled_for_stdout(), expected, "{:?}", (force, no_color, term));
+            assert!(ColorChoice::Always.enabled_for_stdout());
+            assert!(!ColorChoice::Never.enabled_for_stdout());
+        }
+
+        for (name, value) in names.iter().zip(saved) {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_output_has_no_escape_codes() {
+        let path = scratch_log_path("file_no_escapes");
+        let logger = Logger::new(LogConfig {
+            log_filepath: Some(path.clone()),
+            console_flag: false,
+            ..Default::default()
+        });
+        logger.log(LogLevel::Crit, "\x1b[31mcolored\x1b[0m message", CONSOLE_BG_COLOR_RED);
+        logger.log(LogLevel::Info, "plain message", CONSOLE_COLOR_INFO);
+        logger.shutdown().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains('\x1b'));
+        assert_eq!(read_log_lines(&path, "colored message").len(), 1);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_facade_records() {
+        use log::Log;
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            log_level: LogLevel::Warn,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+
+        let emit = |level: log::Level, message: &str| {
+            Log::log(&*logger, &log::Record::builder()
+                .level(level)
+                .target("dep::net")
+                .module_path_static(Some("dep::net::client"))
+                .file_static(Some("src/client.rs"))
+                .line(Some(42))
+                .args(format_args!("{}", message))
+                .build());
+        };
+        emit(log::Level::Debug, "below threshold");
+        emit(log::Level::Warn, "dependency warning");
+        emit(log::Level::Error, "dependency error");
+        assert!(!Log::enabled(&*logger, &log::Metadata::builder().level(log::Level::Info).build()));
+        logger.shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let levels: Vec<_> = records.iter().map(|r| (r.level, r.message.as_str())).collect();
+        assert_eq!(levels, [(LogLevel::Warn, "dependency warning"), (LogLevel::Error, "dependency error")]);
+        assert_eq!(records[0].target, "dep::net");
+        assert_eq!(records[0].module_path.as_deref(), Some("dep::net::client"));
+        assert_eq!(records[0].file.as_deref(), Some("src/client.rs"));
+        assert_eq!(records[0].line, Some(42));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_facade_through_init() {
+        let _global = GLOBAL_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let records = Arc::new(Mutex::new(Vec::new()));
+        mlog::init(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+        assert_eq!(log::max_level(), log::LevelFilter::Info);
+
+        log::warn!(target: "dep::net", "dependency warning");
+        log::debug!(target: "dep::net", "below threshold");
+        mlog::set_level(LogLevel::Error);
+        assert_eq!(log::max_level(), log::LevelFilter::Error);
+        log::warn!(target: "dep::net", "now filtered");
+        log::error!(target: "dep::net", "dependency error");
+        mlog::set_level(LogLevel::Trace);
+        assert_eq!(log::max_level(), log::LevelFilter::Trace);
+        log::trace!(target: "dep::net", "dependency trace");
+        shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let levels: Vec<_> = records.iter().map(|r| (r.level, r.message.as_str())).collect();
+        assert_eq!(levels, [
+            (LogLevel::Warn, "dependency warning"),
+            (LogLevel::Error, "dependency error"),
+            (LogLevel::Trace, "dependency trace"),
+        ]);
+        assert!(records.iter().all(|r| r.target == "dep::net"));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_layer_events_and_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+
+        let subscriber = tracing_subscriber::registry().with(MlogLayer::with_logger(Arc::clone(&logger)));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside spans");
+            let request = tracing::info_span!("request", id = 7, path = "/users");
+            let _request = request.enter();
+            let db = tracing::warn_span!("db");
+            let _db = db.enter();
+            tracing::warn!(rows = 3, "query done");
+            tracing::error!(target: "app::errors", "failed: {}", "timeout");
+        });
+        logger.shutdown().unwrap();
+
+        let records = records.lock().unwrap();
+        let messages: Vec<_> = records.iter().map(|r| (r.level, r.message.as_str())).collect();
+        assert_eq!(messages, [
+            (LogLevel::Info, "outside spans"),
+            (LogLevel::Warn, "request{id=7 path=/users}:db: query done"),
+            (LogLevel::Error, "request{id=7 path=/users}:db: failed: timeout"),
+        ]);
+        assert_eq!(records[1].fields, [("rows".to_string(), Value::I64(3))]);
+        assert_eq!(records[0].target, module_path!());
+        assert_eq!(records[2].target, "app::errors");
+        assert_eq!(records[1].file.as_deref(), Some(file!()));
+        assert!(records[1].line.is_some());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_layer_follows_level_changes() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let logger = Logger::new(LogConfig {
+            console_flag: false,
+            sinks: vec![SinkConfig::new(CollectSink(Arc::clone(&records)))],
+            ..Default::default()
+        });
+
+        // The same callsite is hit before and after the change, so a cached "never" would keep it off
+        let debug = |n: u32| tracing::debug!("debug {}", n);
+        let subscriber = tracing_subscriber::registry().with(MlogLayer::with_logger(Arc::clone(&logger)));
+        tracing::subscriber::with_default(subscriber, || {
+            debug(1);
+            logger.set_level(LogLevel::Debug);
+            debug(2);
+            logger.set_filter(TargetFilter::parse("info", LogLevel::Info).unwrap());
+            debug(3);
+        });
+        logger.shutdown().unwrap();
+
+        let messages: Vec<_> = records.lock().unwrap().iter().map(|r| r.message.clone()).collect();
+        assert_eq!(messages, ["debug 2"]);
+    }
+
     //     #[test]
     // fn test_single_threaded_non_async() {
     //     let config = LogConfig {